@@ -1,3 +1,4 @@
+use crate::interner::Symbol;
 use crate::scanner::{Token, TokenLiteral};
 use std::rc::Rc;
 
@@ -24,12 +25,37 @@ pub enum Expr {
     Get {
         object: Box<Expr>,
         name: Token,
+        id: ExprId,
+    },
+
+    // 'global name' reads a global variable even where a local shadows it, and
+    // 'global name = value' assigns one.
+    Global {
+        name: Token,
+        value: Option<Box<Expr>>,
     },
 
     Grouping {
         expression: Box<Expr>,
     },
 
+    Index {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+
+    IndexSet {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+
+    List {
+        elements: Vec<Expr>,
+    },
+
     Literal {
         value: TokenLiteral,
     },
@@ -40,6 +66,20 @@ pub enum Expr {
         right: Box<Expr>,
     },
 
+    // '{key: value, ...}'. Only parsed where an expression is expected, so a '{' starting a
+    // statement is still a block.
+    Map {
+        brace: Token,
+        entries: Vec<(Expr, Expr)>,
+    },
+
+    Match {
+        keyword: Token,
+        subject: Box<Expr>,
+        arms: Vec<(Expr, Expr)>,
+        default: Option<Box<Expr>>,
+    },
+
     Set {
         object: Box<Expr>,
         name: Token,
@@ -52,11 +92,24 @@ pub enum Expr {
         id: ExprId,
     },
 
+    // 'condition ? then_branch : else_branch', which only evaluates the chosen branch.
+    Ternary {
+        condition: Box<Expr>,
+        question: Token,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>,
+    },
+
     This {
         keyword: Token,
         id: ExprId,
     },
 
+    TypeOf {
+        keyword: Token,
+        operand: Box<Expr>,
+    },
+
     Unary {
         operator: Token,
         right: Box<Expr>,
@@ -77,46 +130,118 @@ pub struct StmtFunction {
     pub name: Token,
     pub params: Vec<Token>,
     pub body: Vec<Stmt>,
+    pub is_generator: bool,
 }
 
 #[derive(Clone)]
 pub enum Stmt {
+    Assert {
+        keyword: Token,
+        condition: Expr,
+        // The condition as written, for the failure message.
+        source: String,
+    },
+
     Block {
         statements: Vec<Stmt>,
     },
 
+    Break {
+        keyword: Token,
+    },
+
     Class {
         name: Token,
         superclass: Option<Expr>,
+        // Classes whose methods are copied into this one, in the order they were listed.
+        mixins: Vec<Expr>,
+        // Fields set on each new instance before 'init' runs.
+        fields: Vec<(Token, Option<Expr>)>,
         methods: Vec<Rc<StmtFunction>>,
     },
 
+    Continue {
+        keyword: Token,
+    },
+
+    Enum {
+        name: Token,
+        members: Vec<Token>,
+    },
+
     Expression(Expr),
 
+    Foreach {
+        keyword: Token,
+        name: Token,
+        iterable: Expr,
+        body: Box<Stmt>,
+    },
+
     Function(Rc<StmtFunction>),
 
     If {
+        keyword: Token,
         condition: Expr,
         then_branch: Box<Stmt>,
         else_branch: Option<Box<Stmt>>,
     },
 
+    Import {
+        keyword: Token,
+        path: Symbol,
+        // The variable a module import binds the imported script's declarations to.
+        name: Option<Token>,
+    },
+
     Print {
+        keyword: Token,
         expression: Expr,
     },
 
+    Repeat {
+        keyword: Token,
+        count: Expr,
+        body: Box<Stmt>,
+    },
+
     Return {
         keyword: Token,
         value: Option<Expr>,
     },
 
+    Throw {
+        keyword: Token,
+        value: Expr,
+    },
+
+    Try {
+        body: Vec<Stmt>,
+        // Bound to the thrown value, or the message of a runtime error, while the catch block
+        // runs.
+        catch_var: Token,
+        catch_body: Vec<Stmt>,
+    },
+
     Var {
         name: Token,
         initializer: Option<Expr>,
     },
 
+    VarList {
+        declarations: Vec<Stmt>,
+    },
+
     While {
+        keyword: Token,
         condition: Expr,
         body: Box<Stmt>,
+        // Run after each iteration; only desugared `for` loops have one.
+        increment: Vec<Expr>,
+    },
+
+    Yield {
+        keyword: Token,
+        value: Expr,
     },
 }
@@ -9,6 +9,8 @@ pub struct Interner {
     pub sym_this: Symbol,
     pub sym_init: Symbol,
     pub sym_super: Symbol,
+    pub sym_name: Symbol,
+    pub sym_ordinal: Symbol,
     interner: RefCell<StringInterner<StringBackend<Symbol>>>,
 }
 
@@ -19,6 +21,8 @@ impl Interner {
             sym_this: interner.get_or_intern("this"),
             sym_init: interner.get_or_intern("init"),
             sym_super: interner.get_or_intern("super"),
+            sym_name: interner.get_or_intern("name"),
+            sym_ordinal: interner.get_or_intern("ordinal"),
             interner: RefCell::new(interner),
         }
     }
@@ -32,6 +36,20 @@ impl Interner {
         )
     }
 
+    // Like resolve, but lends the string to `f` instead of copying it. `f` must not intern
+    // strings, since the interner stays borrowed while it runs.
+    pub fn resolve_with<R>(&self, symbol: Symbol, f: impl FnOnce(&str) -> R) -> R {
+        f(self
+            .interner
+            .borrow()
+            .resolve(symbol)
+            .expect("Resolving an invalid symbol"))
+    }
+
+    pub fn len(&self) -> usize {
+        self.interner.borrow().len()
+    }
+
     pub fn get_or_intern<T>(&self, string: T) -> Symbol
     where
         T: AsRef<str>,
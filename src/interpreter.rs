@@ -1,34 +1,298 @@
 use crate::ast::{Expr, ExprId, ExprVariable, Stmt, StmtFunction};
 use crate::interner::{Interner, Symbol};
+use crate::number_format::{format_exponential, format_g, format_significant};
+use crate::profiler::Profiler;
 use crate::scanner::{Token, TokenLiteral, TokenType};
-use crate::App;
-use rustc_hash::FxHashMap;
+use crate::{App, RuntimeErrorKind};
+use indexmap::{Equivalent, IndexMap};
+use rustc_hash::{FxHashMap, FxHashSet, FxHasher};
 use std::borrow::Borrow;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::rc::{Rc, Weak};
 use std::time;
+use std::{mem, ptr};
 use vec_map::VecMap;
 
+const MAX_CALL_DEPTH: usize = 2048;
+const MAX_POOLED_ENVIRONMENTS: usize = 256;
+
 pub struct Interpreter {
     global_environment: Rc<Environment>,
     environment: Rc<Environment>,
     locals: VecMap<usize>,
+    call_depth: usize,
+    // Optional finer limits on top of MAX_CALL_DEPTH, counted separately for calls of plain
+    // functions and of methods (including constructors).
+    function_depth: usize,
+    method_depth: usize,
+    function_depth_limit: Option<usize>,
+    method_depth_limit: Option<usize>,
+    // Longest string, in bytes, that concatenation may build.
+    max_string_len: Option<usize>,
+    environment_pool: Vec<Rc<Environment>>,
+    // Significant digits used when printing numbers, or None for clox-style %g formatting.
+    precision: Option<usize>,
+    // Inline caches for property reads, keyed by the id of the Get expression: the class of
+    // the instance last read there and the method that name resolved to. Methods never
+    // change after a class is declared, so entries only need to check the class.
+    method_cache: VecMap<(Weak<Class>, Value)>,
+    // Require conditions to be booleans instead of treating every value as truthy or falsey.
+    strict_bool: bool,
+    // Keep the keys of maps in canonical order instead of the order they were added.
+    sorted_maps: bool,
+    // Canonical paths of the scripts run so far, so that each is imported only once.
+    imported: FxHashSet<PathBuf>,
+    // Modules by canonical path, or None while the module is still loading.
+    modules: FxHashMap<PathBuf, Option<Value>>,
+    profiler: Option<Profiler>,
+    test_results: Option<TestResults>,
+    // Loop iterations and calls left to run, if they are limited.
+    step_budget: Option<usize>,
+    // Fail printing and importing, for running untrusted code.
+    io_disabled: bool,
 }
 
 impl Interpreter {
     pub fn new(interner: &Interner) -> Interpreter {
         let global_environment = Rc::new(Environment::new(None));
 
-        global_environment.define(
-            interner.get_or_intern("clock"),
-            Value::Callable(Rc::new(Function::Native(0, |_, _| {
-                if let Ok(n) = time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
-                    Ok(Value::Number(n.as_secs_f64()))
-                } else {
-                    panic!("SystemTime before UNIX_EPOCH.");
+        global_environment.define_native(interner, "clock", 0, |_, _, _, _| {
+            if let Ok(n) = time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
+                Ok(Value::Number(n.as_secs_f64()))
+            } else {
+                panic!("SystemTime before UNIX_EPOCH.");
+            }
+        });
+        global_environment.define_native(interner, "len", 1, |_, interner, paren, arguments| {
+            match &arguments[0] {
+                Value::List(list) => return Ok(Value::Number(RefCell::borrow(list).len() as f64)),
+                Value::Map(map) => return Ok(Value::Number(RefCell::borrow(map).len() as f64)),
+                _ => {}
+            }
+            // Counts characters rather than bytes.
+            match arguments[0].with_str(interner, |string| string.chars().count()) {
+                Some(len) => Ok(Value::Number(len as f64)),
+                None => Err(ErrCause::Error(
+                    paren.clone(),
+                    String::from("Argument to len() must be a string, list or map."),
+                )),
+            }
+        });
+        global_environment.define_native(interner, "push", 2, |_, _, paren, arguments| {
+            match &arguments[0] {
+                Value::List(list) => {
+                    list.borrow_mut().push(arguments[1].clone());
+                    Ok(Value::Nil)
+                }
+                _ => Err(ErrCause::Error(
+                    paren.clone(),
+                    String::from("Can only push onto a list."),
+                )),
+            }
+        });
+        global_environment.define_native(interner, "pop", 1, |_, _, paren, arguments| {
+            match &arguments[0] {
+                Value::List(list) => list.borrow_mut().pop().ok_or_else(|| {
+                    ErrCause::Error(paren.clone(), String::from("Can't pop from an empty list."))
+                }),
+                _ => Err(ErrCause::Error(
+                    paren.clone(),
+                    String::from("Can only pop from a list."),
+                )),
+            }
+        });
+        global_environment.define_native(interner, "substr", 3, native_substr);
+        // The characters of a string as one-character strings.
+        global_environment.define_native(interner, "chars", 1, |_, interner, paren, arguments| {
+            let chars = arguments[0].with_str(interner, |string| {
+                string
+                    .chars()
+                    .map(|c| Value::String(Rc::new(c.to_string())))
+                    .collect()
+            });
+            match chars {
+                Some(chars) => Ok(Value::List(Rc::new(RefCell::new(chars)))),
+                None => Err(ErrCause::Error(
+                    paren.clone(),
+                    String::from("Argument to chars() must be a string."),
+                )),
+            }
+        });
+        // The UTF-8 bytes of a string as numbers.
+        global_environment.define_native(interner, "bytes", 1, |_, interner, paren, arguments| {
+            let bytes = arguments[0].with_str(interner, |string| {
+                string
+                    .bytes()
+                    .map(|b| Value::Number(f64::from(b)))
+                    .collect()
+            });
+            match bytes {
+                Some(bytes) => Ok(Value::List(Rc::new(RefCell::new(bytes)))),
+                None => Err(ErrCause::Error(
+                    paren.clone(),
+                    String::from("Argument to bytes() must be a string."),
+                )),
+            }
+        });
+        // The keys, values and entries of a map as lists, in the order the keys were added.
+        // Entries are lists of a key and its value.
+        global_environment.define_native(interner, "keys", 1, |_, _, paren, arguments| {
+            map_elements(paren, "keys", &arguments[0], |key, _| key.to_value())
+        });
+        global_environment.define_native(interner, "values", 1, |_, _, paren, arguments| {
+            map_elements(paren, "values", &arguments[0], |_, value| value.clone())
+        });
+        global_environment.define_native(interner, "entries", 1, |_, _, paren, arguments| {
+            map_elements(paren, "entries", &arguments[0], |key, value| {
+                Value::List(Rc::new(RefCell::new(vec![key.to_value(), value.clone()])))
+            })
+        });
+        global_environment.define_native(interner, "has", 2, |_, interner, paren, arguments| {
+            match &arguments[0] {
+                Value::Map(map) => {
+                    let map = RefCell::borrow(map);
+                    let found =
+                        MapKey::find(interner, paren, &arguments[1], |key| map.contains_key(&key))?;
+                    Ok(Value::Bool(found))
+                }
+                _ => Err(ErrCause::Error(
+                    paren.clone(),
+                    String::from("First argument to has() must be a map."),
+                )),
+            }
+        });
+        global_environment.define_native(interner, "benchmark", 3, native_benchmark);
+        global_environment.define_native(interner, "map", 2, native_map);
+        global_environment.define_native(interner, "filter", 2, native_filter);
+        global_environment.define_native(interner, "reduce", 3, native_reduce);
+        global_environment.define_native_with_optional(interner, "sort", 1, 2, native_sort);
+        // The line of the call, which is the line being executed when it is used for logging.
+        global_environment.define_native(interner, "currentLine", 0, |_, _, paren, _| {
+            Ok(Value::Number(paren.line as f64))
+        });
+        global_environment.define_native(interner, "deepClone", 1, |_, _, paren, arguments| {
+            deep_clone(paren, &arguments[0], &mut Vec::new())
+        });
+        global_environment.define_native(interner, "deleteField", 2, native_delete_field);
+        global_environment.define_native(interner, "freeze", 1, |_, _, paren, arguments| {
+            match &arguments[0] {
+                Value::Instance(instance) => {
+                    instance.frozen.set(true);
+                    Ok(arguments[0].clone())
+                }
+                _ => Err(ErrCause::Error(
+                    paren.clone(),
+                    String::from("Only instances can be frozen."),
+                )),
+            }
+        });
+        global_environment.define_native(
+            interner,
+            "prettyPrint",
+            1,
+            |interpreter, interner, paren, arguments| {
+                interpreter.check_io(paren)?;
+                let precision = interpreter.precision;
+                println!(
+                    "{}",
+                    pretty_print(interner, &arguments[0], precision, 0, &mut Vec::new())
+                );
+                Ok(Value::Nil)
+            },
+        );
+        global_environment.define_native(interner, "isInteger", 1, |_, _, _, arguments| {
+            Ok(Value::Bool(matches!(
+                arguments[0],
+                Value::Number(num) if num.is_finite() && num.fract() == 0.0
+            )))
+        });
+        global_environment.define_native(interner, "toFixed", 2, |_, _, paren, arguments| {
+            let (num, digits) = number_and_digits(paren, arguments)?;
+            Ok(Value::String(Rc::new(format!("{:.*}", digits, num))))
+        });
+        global_environment.define_native(interner, "toExponential", 2, |_, _, paren, arguments| {
+            let (num, digits) = number_and_digits(paren, arguments)?;
+            Ok(Value::String(Rc::new(format_exponential(num, digits))))
+        });
+        global_environment.define_native(interner, "trunc", 1, |_, _, paren, arguments| {
+            if let Value::Number(num) = arguments[0] {
+                Ok(Value::Number(num.trunc()))
+            } else {
+                Err(ErrCause::Error(
+                    paren.clone(),
+                    String::from("Argument must be a number."),
+                ))
+            }
+        });
+        global_environment.define_native(interner, "floorDiv", 2, |_, _, paren, arguments| {
+            match arguments {
+                // Rounds toward negative infinity, unlike trunc. Dividing by zero follows '/'.
+                [Value::Number(a), Value::Number(b)] => Ok(Value::Number((a / b).floor())),
+                _ => Err(ErrCause::Error(
+                    paren.clone(),
+                    String::from("Arguments must be numbers."),
+                )),
+            }
+        });
+        global_environment.define_native(interner, "approxEqual", 3, |_, _, paren, arguments| {
+            match arguments {
+                [Value::Number(a), Value::Number(b), Value::Number(epsilon)] => {
+                    // Comparisons with NaN are false, and equal infinities have no difference.
+                    Ok(Value::Bool(a == b || (a - b).abs() <= *epsilon))
                 }
-            }))),
+                _ => Err(ErrCause::Error(
+                    paren.clone(),
+                    String::from("Arguments must be numbers."),
+                )),
+            }
+        });
+        global_environment.define_native(
+            interner,
+            "require",
+            2,
+            |interpreter, interner, paren, arguments| match &arguments[1] {
+                Value::String(_) | Value::InternedString(_) => {
+                    let passed = is_truthy(&arguments[0]);
+                    let message = || stringify(interner, &arguments[1], None);
+                    if let Some(results) = &mut interpreter.test_results {
+                        results.record(paren, message(), (!passed).then(message));
+                        Ok(Value::Nil)
+                    } else if passed {
+                        Ok(Value::Nil)
+                    } else {
+                        Err(ErrCause::Error(paren.clone(), message()))
+                    }
+                }
+                _ => Err(ErrCause::Error(
+                    paren.clone(),
+                    String::from("Require message must be a string."),
+                )),
+            },
+        );
+        global_environment.define_native(interner, "setPrecision", 1, native_set_precision);
+        global_environment.define_native(
+            interner,
+            "write",
+            1,
+            |interpreter, interner, paren, arguments| {
+                interpreter.check_io(paren)?;
+                print!(
+                    "{}",
+                    stringify(interner, &arguments[0], interpreter.precision)
+                );
+                io::stdout().flush().expect("Could not flush stdout");
+                Ok(Value::Nil)
+            },
         );
+        global_environment.define_native(interner, "println", 0, |interpreter, _, paren, _| {
+            interpreter.check_io(paren)?;
+            println!();
+            Ok(Value::Nil)
+        });
 
         let environment = Rc::clone(&global_environment);
 
@@ -36,30 +300,280 @@ impl Interpreter {
             global_environment,
             environment,
             locals: VecMap::default(),
+            call_depth: 0,
+            function_depth: 0,
+            method_depth: 0,
+            function_depth_limit: None,
+            method_depth_limit: None,
+            max_string_len: None,
+            environment_pool: Vec::new(),
+            precision: None,
+            method_cache: VecMap::default(),
+            strict_bool: false,
+            sorted_maps: false,
+            imported: FxHashSet::default(),
+            modules: FxHashMap::default(),
+            profiler: None,
+            test_results: None,
+            step_budget: None,
+            io_disabled: false,
         }
     }
 
+    pub fn set_function_depth_limit(&mut self, limit: usize) {
+        self.function_depth_limit = Some(limit);
+    }
+
+    pub fn set_method_depth_limit(&mut self, limit: usize) {
+        self.method_depth_limit = Some(limit);
+    }
+
+    pub fn set_max_string_len(&mut self, limit: usize) {
+        self.max_string_len = Some(limit);
+    }
+
+    pub fn set_step_budget(&mut self, steps: usize) {
+        self.step_budget = Some(steps);
+    }
+
+    pub fn disable_io(&mut self) {
+        self.io_disabled = true;
+    }
+
+    pub fn set_strict_bool(&mut self, strict_bool: bool) {
+        self.strict_bool = strict_bool;
+    }
+
+    pub fn set_sorted_maps(&mut self, sorted_maps: bool) {
+        self.sorted_maps = sorted_maps;
+    }
+
+    // The names and printed values of the global variables, sorted by name, with strings
+    // quoted. Natives are left out, since they are always there.
+    pub fn global_variables(&self, app: &App) -> Vec<(String, String)> {
+        let mut variables: Vec<(String, String)> = self
+            .global_environment
+            .values
+            .borrow()
+            .iter()
+            .filter(|(_, value)| {
+                !matches!(value, Value::Callable(function) if matches!(**function, Function::Native(..)))
+            })
+            .map(|(name, value)| {
+                let value = match value {
+                    Value::String(_) | Value::InternedString(_) => {
+                        format!("\"{}\"", stringify(&app.interner, value, self.precision))
+                    }
+                    _ => stringify(&app.interner, value, self.precision),
+                };
+                (app.interner.resolve(*name), value)
+            })
+            .collect();
+        variables.sort();
+        variables
+    }
+
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    // The time spent in each call stack so far, in the folded format flame graph tools read.
+    pub fn folded_profile(&self, app: &App) -> Option<String> {
+        self.profiler
+            .as_ref()
+            .map(|profiler| profiler.folded(&app.interner))
+    }
+
+    // Records assertions and 'require' calls instead of stopping at the first that fails.
+    pub fn enable_test_results(&mut self) {
+        self.test_results = Some(TestResults::default());
+    }
+
+    // The assertions recorded since the last call, if recording them is enabled.
+    pub(crate) fn take_test_results(&mut self) -> Option<TestResults> {
+        self.test_results.as_mut().map(mem::take)
+    }
+
+    // Returns false if the script was already run, which also ends import cycles.
+    pub(crate) fn mark_imported(&mut self, path: PathBuf) -> bool {
+        self.imported.insert(path)
+    }
+
+    // Environments kept for reuse, which are live without being leaked.
+    pub fn pooled_environments(&self) -> usize {
+        self.environment_pool.len()
+    }
+
+    pub fn define_global(&mut self, app: &App, name: &str, value: LoxValue) {
+        self.global_environment
+            .define(app.interner.get_or_intern(name), value.into_value());
+    }
+
     pub fn interpret(&mut self, app: &App, statements: &[Stmt]) {
+        // Scripts imported from within a module still run in the global scope.
+        let previous = mem::replace(&mut self.environment, Rc::clone(&self.global_environment));
+        self.run_top_level(app, statements);
+        self.environment = previous;
+    }
+
+    // Like interpret, but also returns the value of the last statement if it is an expression
+    // statement, or nil otherwise. Values a host can't hold are returned as their printed
+    // form. Returns None if a runtime error was reported.
+    pub(crate) fn eval(&mut self, app: &App, statements: &[Stmt]) -> Option<LoxValue> {
+        let (last, rest) = match statements.split_last() {
+            Some((Stmt::Expression(expr), rest)) => (Some(expr), rest),
+            _ => (None, statements),
+        };
+
+        let previous = mem::replace(&mut self.environment, Rc::clone(&self.global_environment));
+        let result = if !self.run_top_level(app, rest) {
+            None
+        } else if let Some(expr) = last {
+            match self.evaluate(&app.interner, expr) {
+                Ok(value) => Some(LoxValue::from_value(&app.interner, &value, self.precision)),
+                Err(err) => {
+                    self.report(app, err);
+                    None
+                }
+            }
+        } else {
+            Some(LoxValue::Nil)
+        };
+        self.environment = previous;
+        result
+    }
+
+    // Runs statements in the current environment until one fails. Returns false if a runtime
+    // error was reported.
+    fn run_top_level(&mut self, app: &App, statements: &[Stmt]) -> bool {
         for statement in statements {
-            match self.execute(&app.interner, statement) {
-                Ok(_) => {}
-                Err(ErrCause::Error(token, message)) => {
-                    app.runtime_error(&token, &message);
-                    break;
+            // Imports run a whole script, which needs the app rather than just its interner.
+            if let Stmt::Import {
+                keyword,
+                path,
+                name,
+            } = statement
+            {
+                if self.io_disabled {
+                    app.runtime_error(RuntimeErrorKind::Error, keyword, "IO is disabled.");
+                    return false;
+                }
+                match name {
+                    None => {
+                        if !app.import(self, keyword, &app.interner.resolve(*path)) {
+                            return false;
+                        }
+                    }
+                    Some(name) => match self.import_module(app, keyword, *path) {
+                        Some(module) => self.environment.define(name.lexeme, module),
+                        None => return false,
+                    },
                 }
-                Err(ErrCause::Return(_)) => panic!("Unexpected top level return."),
+                continue;
+            }
+
+            if let Err(err) = self.execute(&app.interner, statement) {
+                self.report(app, err);
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn report(&self, app: &App, err: ErrCause) {
+        match err {
+            ErrCause::Error(token, message) | ErrCause::Limit(token, message) => {
+                app.runtime_error(RuntimeErrorKind::Error, &token, &message);
+            }
+            ErrCause::AssertionFailure(token, message) => {
+                app.runtime_error(RuntimeErrorKind::AssertionFailure, &token, &message);
+            }
+            ErrCause::Thrown(token, value) => {
+                let message = stringify(&app.interner, &value, self.precision);
+                app.runtime_error(RuntimeErrorKind::Error, &token, &message);
+            }
+            ErrCause::Return(_) => panic!("Unexpected top level return."),
+            ErrCause::Break | ErrCause::Continue => {
+                panic!("Unexpected loop control outside a loop.")
+            }
+        }
+    }
+
+    // Runs an imported script in a scope of its own and returns its top-level declarations as
+    // a module. Modules are cached, so importing one again returns the same module.
+    fn import_module(&mut self, app: &App, keyword: &Token, path: Symbol) -> Option<Value> {
+        let path_str = app.interner.resolve(path);
+        let (full_path, canonical, source) = app.read_import(keyword, &path_str)?;
+        match self.modules.get(&canonical) {
+            Some(Some(module)) => return Some(module.clone()),
+            Some(None) => {
+                app.runtime_error(
+                    RuntimeErrorKind::Error,
+                    keyword,
+                    &format!("Import cycle through '{}'.", path_str),
+                );
+                return None;
             }
+            None => {}
         }
+
+        // Marks the module as loading, so that import cycles are detected.
+        self.modules.insert(canonical.clone(), None);
+        let module = app
+            .run_import(full_path, &path_str, || {
+                let statements = app.compile_module(self, &source)?;
+                let environment = self.new_environment(Rc::clone(&self.global_environment));
+                let previous = mem::replace(&mut self.environment, Rc::clone(&environment));
+                let completed = self.run_top_level(app, &statements);
+                self.environment = previous;
+
+                completed.then(|| Value::Module(Rc::new(Module { path, environment })))
+            })
+            .flatten();
+
+        match &module {
+            Some(module) => self.modules.insert(canonical, Some(module.clone())),
+            None => self.modules.remove(&canonical),
+        };
+        module
     }
 
     fn execute(&mut self, interner: &Interner, statement: &Stmt) -> Result<(), ErrCause> {
         match statement {
+            Stmt::Assert {
+                keyword,
+                condition,
+                source,
+            } => {
+                let condition = self.evaluate(interner, condition)?;
+                let passed = self.check_condition(keyword, &condition)?;
+                let message = || format!("Assertion failed: {}", source);
+                if let Some(results) = &mut self.test_results {
+                    results.record(keyword, source.clone(), (!passed).then(message));
+                } else if !passed {
+                    return Err(ErrCause::AssertionFailure(keyword.clone(), message()));
+                }
+            }
             Stmt::Expression(expr) => {
                 self.evaluate(interner, expr)?;
             }
-            Stmt::Print { expression } => {
+            Stmt::Import { keyword, .. } => {
+                return Err(ErrCause::Error(
+                    keyword.clone(),
+                    String::from("Can only import at top level."),
+                ));
+            }
+            Stmt::Print {
+                keyword,
+                expression,
+            } => {
+                self.check_io(keyword)?;
                 let value = self.evaluate(interner, expression)?;
-                println!("{}", stringify(interner, &value));
+                match value {
+                    Value::InternedString(sym) => interner.resolve_with(sym, |s| println!("{}", s)),
+                    _ => println!("{}", stringify(interner, &value, self.precision)),
+                }
             }
             Stmt::Var { name, initializer } => {
                 let value = match initializer {
@@ -68,26 +582,127 @@ impl Interpreter {
                 };
                 self.environment.define(name.lexeme, value);
             }
+            Stmt::VarList { declarations } => {
+                for declaration in declarations {
+                    self.execute(interner, declaration)?;
+                }
+            }
             Stmt::Block { statements } => {
-                let environment = Environment::new(Some(Rc::clone(&self.environment)));
+                let environment = self.new_environment(Rc::clone(&self.environment));
                 self.execute_block(interner, statements, environment)?;
             }
+            Stmt::Try {
+                body,
+                catch_var,
+                catch_body,
+            } => {
+                let environment = self.new_environment(Rc::clone(&self.environment));
+                // Only runtime errors and thrown values are caught; returns, failed
+                // assertions and exceeded limits pass through.
+                let error = match self.execute_block(interner, body, environment) {
+                    Err(ErrCause::Error(_, message)) => Value::String(Rc::new(message)),
+                    Err(ErrCause::Thrown(_, value)) => value,
+                    result => return result,
+                };
+
+                let environment = self.new_environment(Rc::clone(&self.environment));
+                environment.define(catch_var.lexeme, error);
+                self.execute_block(interner, catch_body, environment)?;
+            }
+            Stmt::Break { .. } => return Err(ErrCause::Break),
+            Stmt::Continue { .. } => return Err(ErrCause::Continue),
+            Stmt::Throw { keyword, value } => {
+                let value = self.evaluate(interner, value)?;
+                return Err(ErrCause::Thrown(keyword.clone(), value));
+            }
             Stmt::If {
+                keyword,
                 condition,
                 then_branch,
                 else_branch,
             } => {
-                if is_truthy(&self.evaluate(interner, condition)?) {
+                let condition = self.evaluate(interner, condition)?;
+                if self.check_condition(keyword, &condition)? {
                     self.execute(interner, then_branch)?;
                 } else if let Some(else_branch) = else_branch {
                     self.execute(interner, else_branch)?;
                 }
             }
-            Stmt::While { condition, body } => {
-                while is_truthy(&self.evaluate(interner, condition)?) {
-                    self.execute(interner, body)?;
+            Stmt::While {
+                keyword,
+                condition,
+                body,
+                increment,
+            } => loop {
+                let condition = self.evaluate(interner, condition)?;
+                if !self.check_condition(keyword, &condition)? {
+                    break;
+                }
+
+                self.step(keyword)?;
+                if !loop_control(self.execute(interner, body))? {
+                    break;
+                }
+                for expr in increment {
+                    self.evaluate(interner, expr)?;
+                }
+            },
+            Stmt::Repeat {
+                keyword,
+                count,
+                body,
+            } => {
+                let count = match self.evaluate(interner, count)? {
+                    Value::Number(num) if num >= 0.0 && num.fract() == 0.0 => num as u64,
+                    _ => {
+                        return Err(ErrCause::Error(
+                            keyword.clone(),
+                            String::from("Repeat count must be a non-negative integer."),
+                        ))
+                    }
+                };
+
+                for _ in 0..count {
+                    self.step(keyword)?;
+                    if !loop_control(self.execute(interner, body))? {
+                        break;
+                    }
+                }
+            }
+            Stmt::Foreach {
+                keyword,
+                name,
+                iterable,
+                body,
+            } => {
+                let iterable = self.evaluate(interner, iterable)?;
+                let mut index = 0;
+
+                loop {
+                    let Some(value) = self.next_element(interner, keyword, &iterable, index)?
+                    else {
+                        break;
+                    };
+                    index += 1;
+
+                    self.step(keyword)?;
+                    let environment = self.new_environment(Rc::clone(&self.environment));
+                    environment.define(name.lexeme, value);
+                    let result =
+                        self.execute_block(interner, std::slice::from_ref(body), environment);
+                    if !loop_control(result)? {
+                        break;
+                    }
                 }
             }
+            // Generator bodies are run by resume, so this is only reached if the resolver
+            // let a stray 'yield' through.
+            Stmt::Yield { keyword, .. } => {
+                return Err(ErrCause::Error(
+                    keyword.clone(),
+                    String::from("Can't yield outside a generator function."),
+                ))
+            }
             Stmt::Function(function_stmt) => {
                 let function = Value::Callable(Rc::new(Function::Declared(
                     function_stmt.clone(),
@@ -101,25 +716,41 @@ impl Interpreter {
                 name,
                 methods,
                 superclass,
+                mixins,
+                fields,
             } => {
+                let mut mixin_classes = Vec::new();
+                for mixin in mixins {
+                    match self.evaluate(interner, mixin)?.to_class() {
+                        Some(class) if !class.is_enum => mixin_classes.push(class),
+                        _ => {
+                            return Err(ErrCause::Error(
+                                class_clause_token(name, mixin).clone(),
+                                String::from("Mixin must be a class."),
+                            ))
+                        }
+                    }
+                }
+
                 let superclass_value = if let Some(superclass) = superclass {
                     let value = self.evaluate(interner, superclass)?;
-                    if value.is_class() {
-                        Some(value)
-                    } else if let Expr::Variable(_, superclass) = superclass {
+                    if !value.is_class() {
                         return Err(ErrCause::Error(
-                            superclass.name.clone(),
+                            class_clause_token(name, superclass).clone(),
                             String::from("Superclass must be a class."),
                         ));
-                    } else {
-                        unreachable!();
                     }
+                    Some(value)
                 } else {
                     None
                 };
 
                 self.environment.define(name.lexeme, Value::Nil);
 
+                // Field initializers are resolved outside of the class's scopes, so they
+                // are evaluated in the environment the class is declared in.
+                let field_environment = Rc::clone(&self.environment);
+
                 let environment = if let Some(superclass) = &superclass_value {
                     let environment = Rc::new(Environment::new(Some(Rc::clone(&self.environment))));
                     environment.define(interner.sym_super, superclass.clone());
@@ -128,13 +759,20 @@ impl Interpreter {
                     Rc::clone(&self.environment)
                 };
 
-                let mut initializer_arity = None;
+                // Later mixins override earlier ones, and the class's own methods override
+                // them all. Mixin methods keep the closure they were declared in, so 'super'
+                // inside them still refers to the mixin's superclass.
                 let mut class_methods = FxHashMap::default();
+                for mixin in &mixin_classes {
+                    class_methods.extend(
+                        mixin
+                            .methods
+                            .iter()
+                            .map(|(name, method)| (*name, method.clone())),
+                    );
+                }
                 for method in methods {
                     let is_initializer = method.name.lexeme == interner.sym_init;
-                    if is_initializer {
-                        initializer_arity = Some(method.params.len());
-                    }
                     let function = Value::Callable(Rc::new(Function::Declared(
                         method.clone(),
                         Rc::clone(&environment),
@@ -145,23 +783,21 @@ impl Interpreter {
 
                 let superclass = superclass_value.and_then(|superclass| superclass.to_class());
 
-                let initializer_arity = initializer_arity
+                let initializer_arity = class_methods
+                    .get(&interner.sym_init)
+                    .cloned()
                     .or_else(|| {
-                        superclass.as_ref().and_then(|superclass| {
-                            superclass.find_method(interner.sym_init).and_then(|init| {
-                                if let Value::Callable(function) = init {
-                                    if let Function::Declared(stmt_function, ..) =
-                                        Rc::borrow(&function)
-                                    {
-                                        Some(stmt_function.params.len())
-                                    } else {
-                                        None
-                                    }
-                                } else {
-                                    None
-                                }
-                            })
-                        })
+                        superclass
+                            .as_ref()
+                            .and_then(|superclass| superclass.find_method(interner.sym_init))
+                    })
+                    .and_then(|init| {
+                        if let Value::Callable(function) = init {
+                            if let Function::Declared(stmt_function, ..) = Rc::borrow(&function) {
+                                return Some(stmt_function.params.len());
+                            }
+                        }
+                        None
                     })
                     .unwrap_or(0);
 
@@ -171,11 +807,51 @@ impl Interpreter {
                         name: name.lexeme,
                         methods: class_methods,
                         superclass,
+                        fields: fields
+                            .iter()
+                            .map(|(name, initializer)| (name.lexeme, initializer.clone()))
+                            .collect(),
+                        field_environment,
+                        inherited_methods: RefCell::new(FxHashMap::default()),
+                        is_enum: false,
                     }),
                 )));
 
                 self.environment.assign(interner, name, class)?;
             }
+            Stmt::Enum { name, members } => {
+                // Enums have no fields to initialize, so they don't need to hold on to the
+                // environment they are declared in.
+                let class = Rc::new(Class {
+                    name: name.lexeme,
+                    methods: FxHashMap::default(),
+                    superclass: None,
+                    fields: Vec::new(),
+                    field_environment: Rc::clone(&self.global_environment),
+                    inherited_methods: RefCell::new(FxHashMap::default()),
+                    is_enum: true,
+                });
+
+                // Each member is a unique instance of the enum, so members compare by identity.
+                let mut enum_members = FxHashMap::default();
+                for (ordinal, member) in members.iter().enumerate() {
+                    let instance = Instance::new(Rc::clone(&class));
+                    instance
+                        .fields
+                        .borrow_mut()
+                        .insert(interner.sym_name, Value::InternedString(member.lexeme));
+                    instance
+                        .fields
+                        .borrow_mut()
+                        .insert(interner.sym_ordinal, Value::Number(ordinal as f64));
+                    enum_members.insert(member.lexeme, Value::Instance(Rc::new(instance)));
+                }
+
+                self.environment.define(
+                    name.lexeme,
+                    Value::Callable(Rc::new(Function::Enum(class, enum_members))),
+                );
+            }
             Stmt::Return { value, .. } => {
                 let return_value = match value {
                     Some(value_expr) => self.evaluate(interner, value_expr)?,
@@ -192,9 +868,9 @@ impl Interpreter {
         &mut self,
         interner: &Interner,
         statements: &[Stmt],
-        environment: Environment,
+        environment: Rc<Environment>,
     ) -> Result<(), ErrCause> {
-        let previous = std::mem::replace(&mut self.environment, Rc::new(environment));
+        let previous = std::mem::replace(&mut self.environment, environment);
 
         let mut ret = Ok(());
         for statement in statements {
@@ -204,11 +880,378 @@ impl Interpreter {
             }
         }
 
-        self.environment = previous;
+        let finished = std::mem::replace(&mut self.environment, previous);
+        self.recycle_environment(finished);
 
         ret
     }
 
+    // The element of a list at the index, or the next value of a generator, which ignores the
+    // index. None once there are no more.
+    fn next_element(
+        &mut self,
+        interner: &Interner,
+        keyword: &Token,
+        iterable: &Value,
+        index: usize,
+    ) -> Result<Option<Value>, ErrCause> {
+        match iterable {
+            Value::Generator(generator) => self.resume_generator(interner, keyword, generator),
+            // Elements pushed by the body are visited too.
+            Value::List(list) => Ok(RefCell::borrow(list).get(index).cloned()),
+            _ => Err(ErrCause::Error(
+                keyword.clone(),
+                String::from("Can only iterate over generators and lists."),
+            )),
+        }
+    }
+
+    // Runs a generator's body up to its next 'yield' and returns the value yielded, or None
+    // once the body has finished.
+    fn resume_generator(
+        &mut self,
+        interner: &Interner,
+        token: &Token,
+        generator: &Generator,
+    ) -> Result<Option<Value>, ErrCause> {
+        let mut frames = match generator.state.replace(GeneratorState::Running) {
+            GeneratorState::Suspended(frames) => frames,
+            GeneratorState::Running => {
+                return Err(ErrCause::Error(
+                    token.clone(),
+                    String::from("Generator is already running."),
+                ))
+            }
+            GeneratorState::Finished => {
+                generator.state.replace(GeneratorState::Finished);
+                return Ok(None);
+            }
+        };
+
+        // Generators iterating over generators recurse like calls do.
+        if self.call_depth >= MAX_CALL_DEPTH {
+            generator.state.replace(GeneratorState::Suspended(frames));
+            return Err(ErrCause::Error(
+                token.clone(),
+                String::from("Stack overflow."),
+            ));
+        }
+        self.call_depth += 1;
+        let result = self.resume_block(interner, &generator.function.body, &mut frames, 0);
+        self.call_depth -= 1;
+
+        match result {
+            Ok(Some(value)) => {
+                generator.state.replace(GeneratorState::Suspended(frames));
+                Ok(Some(value))
+            }
+            Ok(None) | Err(ErrCause::Return(_)) => {
+                generator.state.replace(GeneratorState::Finished);
+                Ok(None)
+            }
+            Err(err) => {
+                generator.state.replace(GeneratorState::Finished);
+                Err(err)
+            }
+        }
+    }
+
+    // Runs a statement of a generator body until it finishes or reaches a 'yield', and returns
+    // the value yielded, if any. `frames[level]` is the statement's frame: if it is there, the
+    // generator stopped inside the statement, which picks up where it left off. Statements
+    // that can't contain a 'yield' run as usual.
+    fn resume(
+        &mut self,
+        interner: &Interner,
+        statement: &Stmt,
+        frames: &mut Vec<Frame>,
+        level: usize,
+    ) -> Result<Option<Value>, ErrCause> {
+        let result = self.resume_statement(interner, statement, frames, level);
+        if !matches!(result, Ok(Some(_))) {
+            frames.truncate(level);
+        }
+        result
+    }
+
+    fn resume_statement(
+        &mut self,
+        interner: &Interner,
+        statement: &Stmt,
+        frames: &mut Vec<Frame>,
+        level: usize,
+    ) -> Result<Option<Value>, ErrCause> {
+        let resuming = frames.len() > level;
+
+        match statement {
+            Stmt::Block { statements } => {
+                if !resuming {
+                    let environment = self.new_environment(Rc::clone(&self.environment));
+                    frames.push(Frame::new(0, environment));
+                }
+                self.resume_block(interner, statements, frames, level)
+            }
+            Stmt::If {
+                keyword,
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if !resuming {
+                    let condition = self.evaluate(interner, condition)?;
+                    let branch = usize::from(!self.check_condition(keyword, &condition)?);
+                    frames.push(Frame::new(branch, Rc::clone(&self.environment)));
+                }
+                match (frames[level].index, else_branch) {
+                    (0, _) => self.resume(interner, then_branch, frames, level + 1),
+                    (_, Some(else_branch)) => self.resume(interner, else_branch, frames, level + 1),
+                    (_, None) => Ok(None),
+                }
+            }
+            Stmt::While {
+                keyword,
+                condition,
+                body,
+                increment,
+            } => {
+                if !resuming {
+                    frames.push(Frame::new(0, Rc::clone(&self.environment)));
+                }
+                loop {
+                    // Unless the generator stopped inside the body, an iteration starts.
+                    if frames.len() == level + 1 {
+                        let condition = self.evaluate(interner, condition)?;
+                        if !self.check_condition(keyword, &condition)? {
+                            return Ok(None);
+                        }
+                        self.step(keyword)?;
+                    }
+
+                    match self.resume(interner, body, frames, level + 1) {
+                        Ok(None) | Err(ErrCause::Continue) => {}
+                        Err(ErrCause::Break) => return Ok(None),
+                        result => return result,
+                    }
+                    for expr in increment {
+                        self.evaluate(interner, expr)?;
+                    }
+                }
+            }
+            Stmt::Repeat {
+                keyword,
+                count,
+                body,
+            } => {
+                if !resuming {
+                    let count = match self.evaluate(interner, count)? {
+                        Value::Number(num) if num >= 0.0 && num.fract() == 0.0 => num as usize,
+                        _ => {
+                            return Err(ErrCause::Error(
+                                keyword.clone(),
+                                String::from("Repeat count must be a non-negative integer."),
+                            ))
+                        }
+                    };
+                    frames.push(Frame::new(count, Rc::clone(&self.environment)));
+                }
+                loop {
+                    // The frame counts the iterations left to start.
+                    if frames.len() == level + 1 {
+                        if frames[level].index == 0 {
+                            return Ok(None);
+                        }
+                        frames[level].index -= 1;
+                        self.step(keyword)?;
+                    }
+
+                    match self.resume(interner, body, frames, level + 1) {
+                        Ok(None) | Err(ErrCause::Continue) => {}
+                        Err(ErrCause::Break) => return Ok(None),
+                        result => return result,
+                    }
+                }
+            }
+            Stmt::Foreach {
+                keyword,
+                name,
+                iterable,
+                body,
+            } => {
+                if !resuming {
+                    let iterable = self.evaluate(interner, iterable)?;
+                    let mut frame = Frame::new(0, Rc::clone(&self.environment));
+                    frame.iterable = iterable;
+                    frames.push(frame);
+                }
+                loop {
+                    // The frame holds the iterable and the index of the next element, and the
+                    // frame after it the environment of the iteration.
+                    if frames.len() == level + 1 {
+                        let iterable = frames[level].iterable.clone();
+                        let index = frames[level].index;
+                        let Some(value) = self.next_element(interner, keyword, &iterable, index)?
+                        else {
+                            return Ok(None);
+                        };
+                        frames[level].index += 1;
+
+                        self.step(keyword)?;
+                        let environment = self.new_environment(Rc::clone(&self.environment));
+                        environment.define(name.lexeme, value);
+                        frames.push(Frame::new(0, environment));
+                    }
+
+                    let body = std::slice::from_ref(body.as_ref());
+                    match self.resume_block(interner, body, frames, level + 1) {
+                        Ok(None) | Err(ErrCause::Continue) => {}
+                        Err(ErrCause::Break) => return Ok(None),
+                        result => return result,
+                    }
+                }
+            }
+            Stmt::Try {
+                body,
+                catch_var,
+                catch_body,
+            } => {
+                // The frame tells whether the catch block runs, and the frame after it is the
+                // environment of the block running.
+                if !resuming {
+                    frames.push(Frame::new(0, Rc::clone(&self.environment)));
+                    let environment = self.new_environment(Rc::clone(&self.environment));
+                    frames.push(Frame::new(0, environment));
+                }
+                if frames[level].index == 0 {
+                    let error = match self.resume_block(interner, body, frames, level + 1) {
+                        Err(ErrCause::Error(_, message)) => Value::String(Rc::new(message)),
+                        Err(ErrCause::Thrown(_, value)) => value,
+                        result => return result,
+                    };
+
+                    frames[level].index = 1;
+                    let environment = self.new_environment(Rc::clone(&self.environment));
+                    environment.define(catch_var.lexeme, error);
+                    frames.push(Frame::new(0, environment));
+                }
+                self.resume_block(interner, catch_body, frames, level + 1)
+            }
+            Stmt::Yield { value, .. } => {
+                // Resuming after the value was handed out finishes the 'yield'.
+                if resuming {
+                    return Ok(None);
+                }
+                let value = self.evaluate(interner, value)?;
+                frames.push(Frame::new(0, Rc::clone(&self.environment)));
+                Ok(Some(value))
+            }
+            _ => self.execute(interner, statement).map(|()| None),
+        }
+    }
+
+    // Like execute_block, for the block whose frame is `frames[level]`, which holds the index
+    // of the statement to run next and the block's environment.
+    fn resume_block(
+        &mut self,
+        interner: &Interner,
+        statements: &[Stmt],
+        frames: &mut Vec<Frame>,
+        level: usize,
+    ) -> Result<Option<Value>, ErrCause> {
+        let environment = Rc::clone(&frames[level].environment);
+        let previous = mem::replace(&mut self.environment, environment);
+
+        let mut result = Ok(None);
+        while let Some(statement) = statements.get(frames[level].index) {
+            result = self.resume(interner, statement, frames, level + 1);
+            if !matches!(result, Ok(None)) {
+                break;
+            }
+            frames[level].index += 1;
+        }
+
+        let current = mem::replace(&mut self.environment, previous);
+        if !matches!(result, Ok(Some(_))) {
+            frames.truncate(level);
+            self.recycle_environment(current);
+        }
+
+        result
+    }
+
+    // Sets the fields declared in the class body, starting with those of the outermost
+    // superclass so that subclasses can override their defaults.
+    fn initialize_fields(
+        &mut self,
+        interner: &Interner,
+        class: &Class,
+        instance: &Instance,
+    ) -> Result<(), ErrCause> {
+        if let Some(superclass) = &class.superclass {
+            self.initialize_fields(interner, superclass, instance)?;
+        }
+
+        if class.fields.is_empty() {
+            return Ok(());
+        }
+
+        let previous = mem::replace(&mut self.environment, Rc::clone(&class.field_environment));
+        let mut result = Ok(());
+        for (name, initializer) in &class.fields {
+            let value = match initializer {
+                Some(initializer) => match self.evaluate(interner, initializer) {
+                    Ok(value) => value,
+                    Err(err) => {
+                        result = Err(err);
+                        break;
+                    }
+                },
+                None => Value::Nil,
+            };
+            instance.fields.borrow_mut().insert(*name, value);
+        }
+        self.environment = previous;
+
+        result
+    }
+
+    fn find_method(&mut self, id: ExprId, class: &Rc<Class>, name: &Token) -> Option<Value> {
+        if let Some((cached_class, method)) = self.method_cache.get(id.0) {
+            // A cached class can't be freed and replaced at the same address, since the
+            // weak reference keeps its allocation alive.
+            if ptr::eq(cached_class.as_ptr(), Rc::as_ptr(class)) {
+                return Some(method.clone());
+            }
+        }
+
+        let method = class.find_method(name.lexeme)?;
+        self.method_cache
+            .insert(id.0, (Rc::downgrade(class), method.clone()));
+        Some(method)
+    }
+
+    fn new_environment(&mut self, enclosing: Rc<Environment>) -> Rc<Environment> {
+        match self.environment_pool.pop() {
+            Some(mut environment) => {
+                // Pooled environments are never shared, see recycle_environment.
+                Rc::get_mut(&mut environment).unwrap().enclosing = Some(enclosing);
+                environment
+            }
+            None => Rc::new(Environment::new(Some(enclosing))),
+        }
+    }
+
+    fn recycle_environment(&mut self, mut environment: Rc<Environment>) {
+        // Closures created inside a block keep its environment alive, so only environments
+        // nobody else references anymore can be reused.
+        if self.environment_pool.len() < MAX_POOLED_ENVIRONMENTS {
+            if let Some(unique) = Rc::get_mut(&mut environment) {
+                unique.values.get_mut().clear();
+                unique.enclosing = None;
+                self.environment_pool.push(environment);
+            }
+        }
+    }
+
     fn evaluate(&mut self, interner: &Interner, expr: &Expr) -> Result<Value, ErrCause> {
         match expr {
             Expr::Binary {
@@ -239,26 +1282,13 @@ impl Interpreter {
                         (Value::Number(left_num), Value::Number(right_num)) => {
                             Ok(Value::Number(left_num + right_num))
                         }
-                        (Value::String(left_str), Value::String(right_str)) => {
-                            Ok(Value::String(Rc::from((&*left_str).clone() + &*right_str)))
-                        }
-                        (Value::InternedString(left_str), Value::String(right_str)) => Ok(
-                            Value::String(Rc::from(interner.resolve(left_str) + &*right_str)),
-                        ),
-                        (Value::String(left_str), Value::InternedString(right_str)) => {
-                            Ok(Value::String(Rc::from(
-                                (&*left_str).clone() + &interner.resolve(right_str),
-                            )))
-                        }
-                        (Value::InternedString(left_str), Value::InternedString(right_str)) => {
-                            Ok(Value::String(Rc::from(
-                                interner.resolve(left_str) + &interner.resolve(right_str),
-                            )))
-                        }
-                        _ => Err(ErrCause::Error(
-                            operator.clone(),
-                            String::from("Operands must be two numbers or two strings."),
-                        )),
+                        (left, right) => match left.with_strs(&right, interner, concat) {
+                            Some(string) => self.new_string(operator, string),
+                            None => Err(ErrCause::Error(
+                                operator.clone(),
+                                String::from("Operands must be two numbers or two strings."),
+                            )),
+                        },
                     },
                     TokenType::Greater => {
                         let (left_num, right_num) =
@@ -286,6 +1316,88 @@ impl Interpreter {
                 }
             }
             Expr::Grouping { expression } => self.evaluate(interner, expression),
+            Expr::List { elements } => {
+                let mut values = Vec::with_capacity(elements.len());
+                for element in elements {
+                    values.push(self.evaluate(interner, element)?);
+                }
+                Ok(Value::List(Rc::new(RefCell::new(values))))
+            }
+            Expr::Map { brace, entries } => {
+                let mut map = LoxMap::default();
+                for (key, value) in entries {
+                    let key = self.evaluate(interner, key)?;
+                    let key = MapKey::new(interner, brace, &key)?;
+                    let value = self.evaluate(interner, value)?;
+                    // Later entries replace the values of earlier ones with the same key,
+                    // which keep their place.
+                    self.insert_entry(&mut map, key, value);
+                }
+                Ok(Value::Map(Rc::new(RefCell::new(map))))
+            }
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                let object = self.evaluate(interner, object)?;
+                let index = self.evaluate(interner, index)?;
+
+                match &object {
+                    Value::List(list) => {
+                        let list = RefCell::borrow(list);
+                        let index = list_index(bracket, &index, list.len())?;
+                        Ok(list[index].clone())
+                    }
+                    Value::Map(map) => {
+                        let map = RefCell::borrow(map);
+                        let value =
+                            MapKey::find(interner, bracket, &index, |key| map.get(&key).cloned())?;
+                        value.ok_or_else(|| {
+                            ErrCause::Error(
+                                bracket.clone(),
+                                format!(
+                                    "Undefined key {}.",
+                                    stringify_element(interner, &index, None, &mut Vec::new())
+                                ),
+                            )
+                        })
+                    }
+                    _ => Err(ErrCause::Error(
+                        bracket.clone(),
+                        String::from("Only lists and maps can be indexed."),
+                    )),
+                }
+            }
+            Expr::IndexSet {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                let object = self.evaluate(interner, object)?;
+                let index = self.evaluate(interner, index)?;
+
+                match &object {
+                    Value::List(list) => {
+                        let value = self.evaluate(interner, value)?;
+                        let mut list = list.borrow_mut();
+                        let index = list_index(bracket, &index, list.len())?;
+                        list[index] = value.clone();
+                        Ok(value)
+                    }
+                    Value::Map(map) => {
+                        let key = MapKey::new(interner, bracket, &index)?;
+                        let value = self.evaluate(interner, value)?;
+                        self.insert_entry(&mut map.borrow_mut(), key, value.clone());
+                        Ok(value)
+                    }
+                    _ => Err(ErrCause::Error(
+                        bracket.clone(),
+                        String::from("Only lists and maps can be indexed."),
+                    )),
+                }
+            }
             Expr::Literal { value } => match value {
                 TokenLiteral::String(sym) => Ok(Value::InternedString(*sym)),
                 TokenLiteral::Number(num) => Ok(Value::Number(*num)),
@@ -296,7 +1408,15 @@ impl Interpreter {
                 let right = self.evaluate(interner, right)?;
 
                 match operator.token_type {
-                    TokenType::Bang => Ok(Value::Bool(!is_truthy(&right))),
+                    TokenType::Bang => {
+                        if self.strict_bool && !matches!(right, Value::Bool(_)) {
+                            return Err(ErrCause::Error(
+                                operator.clone(),
+                                String::from("Operand must be a boolean."),
+                            ));
+                        }
+                        Ok(Value::Bool(!is_truthy(&right)))
+                    }
                     TokenType::Minus => {
                         let num = self.check_number_operand(operator, &right);
                         Ok(Value::Number(-(num?)))
@@ -304,7 +1424,23 @@ impl Interpreter {
                     _ => panic!("Unexpected unary operator token."),
                 }
             }
+            Expr::TypeOf { operand, .. } => {
+                let operand = self.evaluate(interner, operand)?;
+                Ok(Value::InternedString(
+                    interner.get_or_intern(type_name(&operand)),
+                ))
+            }
             Expr::Variable(id, ExprVariable { name }) => self.look_up_variable(interner, name, *id),
+            Expr::Global { name, value: None } => self.global_environment.get(interner, name),
+            Expr::Global {
+                name,
+                value: Some(value),
+            } => {
+                let value = self.evaluate(interner, value)?;
+                self.global_environment
+                    .assign(interner, name, value.clone())?;
+                Ok(value)
+            }
             Expr::Assign { name, value, id } => {
                 let value = self.evaluate(interner, value)?;
                 if let Some(distance) = self.locals.get(id.0).cloned() {
@@ -321,6 +1457,19 @@ impl Interpreter {
                 }
                 Ok(value)
             }
+            Expr::Ternary {
+                condition,
+                question,
+                then_branch,
+                else_branch,
+            } => {
+                let condition = self.evaluate(interner, condition)?;
+                if self.check_condition(question, &condition)? {
+                    self.evaluate(interner, then_branch)
+                } else {
+                    self.evaluate(interner, else_branch)
+                }
+            }
             Expr::Logical {
                 left,
                 operator,
@@ -328,22 +1477,52 @@ impl Interpreter {
             } => {
                 let left = self.evaluate(interner, left)?;
 
+                let left_truthy = self.check_condition(operator, &left)?;
                 if operator.token_type == TokenType::Or {
-                    if is_truthy(&left) {
+                    if left_truthy {
                         return Ok(left);
                     }
-                } else if !is_truthy(&left) {
+                } else if !left_truthy {
                     return Ok(left);
                 }
 
-                self.evaluate(interner, right)
+                // In strict mode both operands are booleans, so the result is one too.
+                let right = self.evaluate(interner, right)?;
+                if self.strict_bool {
+                    self.check_condition(operator, &right)?;
+                }
+                Ok(right)
+            }
+            Expr::Match {
+                keyword,
+                subject,
+                arms,
+                default,
+            } => {
+                let subject = self.evaluate(interner, subject)?;
+
+                for (value, result) in arms {
+                    let value = self.evaluate(interner, value)?;
+                    if is_equal(&subject, &value, interner) {
+                        return self.evaluate(interner, result);
+                    }
+                }
+
+                match default {
+                    Some(default) => self.evaluate(interner, default),
+                    None => Err(ErrCause::Error(
+                        keyword.clone(),
+                        String::from("No match arm matched and no else branch."),
+                    )),
+                }
             }
             Expr::Call {
                 callee,
                 paren,
                 arguments,
             } => {
-                let callee = self.evaluate(interner, callee)?;
+                let callee_expr = callee;
+                let callee = self.evaluate(interner, callee_expr)?;
 
                 let mut argument_values = Vec::new();
                 for argument in arguments {
@@ -351,20 +1530,31 @@ impl Interpreter {
                 }
 
                 if let Value::Callable(function) = callee {
-                    if argument_values.len() == function.arity() {
-                        let f: &Function = Rc::borrow(&function);
-                        match f.call(self, interner, &argument_values) {
-                            Err(ErrCause::Return(value)) => Ok(value),
-                            result => result,
-                        }
+                    let (required, arity) = (function.required_arity(), function.arity());
+                    if (required..=arity).contains(&argument_values.len()) {
+                        self.call(interner, paren, &function, &argument_values)
                     } else {
+                        let expected = if required == arity {
+                            arity.to_string()
+                        } else {
+                            format!("{} to {}", required, arity)
+                        };
                         let message = format!(
-                            "Expected {} arguments but got {}.",
-                            function.arity(),
-                            argument_values.len()
+                            "Expected {} arguments but got {} in call to {}.",
+                            expected,
+                            argument_values.len(),
+                            function.describe(interner)
                         );
                         Err(ErrCause::Error(paren.clone(), message))
                     }
+                } else if let Expr::Get { name, .. } = callee_expr.as_ref() {
+                    Err(ErrCause::Error(
+                        name.clone(),
+                        format!(
+                            "Property '{}' is not callable.",
+                            interner.resolve(name.lexeme)
+                        ),
+                    ))
                 } else {
                     Err(ErrCause::Error(
                         paren.clone(),
@@ -372,10 +1562,51 @@ impl Interpreter {
                     ))
                 }
             }
-            Expr::Get { object, name } => {
+            Expr::Get { object, name, id } => {
                 let object = self.evaluate(interner, object)?;
                 if let Value::Instance(instance) = object {
-                    instance.get(interner, name)
+                    if let Some(value) = instance.get_field(interner, name.lexeme) {
+                        Ok(value)
+                    } else if let Some(method) = self.find_method(*id, &instance.class, name) {
+                        Ok(instance.bind_method(interner, &method))
+                    } else {
+                        Err(ErrCause::Error(
+                            name.clone(),
+                            format!("Undefined property '{}'.", interner.resolve(name.lexeme)),
+                        ))
+                    }
+                } else if let Some(members) = object.enum_members() {
+                    members.get(&name.lexeme).cloned().ok_or_else(|| {
+                        ErrCause::Error(
+                            name.clone(),
+                            format!("Undefined property '{}'.", interner.resolve(name.lexeme)),
+                        )
+                    })
+                } else if let Some(Value::Callable(method)) = object
+                    .to_class()
+                    .and_then(|class| class.find_method(name.lexeme))
+                {
+                    // Reading a method off its class gives it unbound, taking the receiver
+                    // as an extra first argument.
+                    let class = object.to_class().unwrap();
+                    Ok(Value::Callable(Rc::new(Function::Unbound(class, method))))
+                } else if let Value::Module(module) = object {
+                    let value = module
+                        .environment
+                        .values
+                        .borrow()
+                        .get(&name.lexeme)
+                        .cloned();
+                    value.ok_or_else(|| {
+                        ErrCause::Error(
+                            name.clone(),
+                            format!(
+                                "Undefined member '{}' in module '{}'.",
+                                interner.resolve(name.lexeme),
+                                interner.resolve(module.path)
+                            ),
+                        )
+                    })
                 } else {
                     Err(ErrCause::Error(
                         name.clone(),
@@ -392,7 +1623,7 @@ impl Interpreter {
 
                 if let Value::Instance(instance) = &mut object {
                     let value = self.evaluate(interner, value)?;
-                    instance.set(name, value.clone());
+                    instance.set(interner, name, value.clone())?;
                     Ok(value)
                 } else {
                     Err(ErrCause::Error(
@@ -405,11 +1636,14 @@ impl Interpreter {
             Expr::Super { method, id, .. } => {
                 let distance = self.locals.get(id.0).cloned().unwrap();
                 let superclass =
-                    Environment::get_at(&self.environment, distance, interner.sym_super);
+                    Environment::get_at(&self.environment, distance, interner.sym_super).unwrap();
                 let object =
-                    Environment::get_at(&self.environment, distance - 1, interner.sym_this);
+                    Environment::get_at(&self.environment, distance - 1, interner.sym_this)
+                        .unwrap();
                 let method_value = if let Value::Callable(function) = superclass {
-                    if let Function::Class(_, class) = Rc::borrow(&function) {
+                    if let Function::Class(_, class) | Function::Enum(class, _) =
+                        Rc::borrow(&function)
+                    {
                         class.find_method(method.lexeme)
                     } else {
                         unreachable!()
@@ -431,25 +1665,146 @@ impl Interpreter {
         }
     }
 
+    fn call(
+        &mut self,
+        interner: &Interner,
+        paren: &Token,
+        function: &Function,
+        arguments: &[Value],
+    ) -> Result<Value, ErrCause> {
+        self.step(paren)?;
+        if self.call_depth >= MAX_CALL_DEPTH {
+            return Err(ErrCause::Error(
+                paren.clone(),
+                String::from("Stack overflow."),
+            ));
+        }
+
+        let is_method = function.is_method(interner);
+        let (depth, limit, kind) = if is_method {
+            (self.method_depth, self.method_depth_limit, "Method")
+        } else {
+            (self.function_depth, self.function_depth_limit, "Function")
+        };
+        if limit.is_some_and(|limit| depth >= limit) {
+            return Err(ErrCause::Limit(
+                paren.clone(),
+                format!("{} call depth limit exceeded.", kind),
+            ));
+        }
+
+        self.call_depth += 1;
+        if is_method {
+            self.method_depth += 1;
+        } else {
+            self.function_depth += 1;
+        }
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter(function.profile_name(interner));
+        }
+
+        let result = match function.call(self, interner, paren, arguments) {
+            Err(ErrCause::Return(value)) => Ok(value),
+            result => result,
+        };
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.exit();
+        }
+
+        self.call_depth -= 1;
+        if is_method {
+            self.method_depth -= 1;
+        } else {
+            self.function_depth -= 1;
+        }
+
+        result
+    }
+
     fn resolve(&mut self, id: ExprId, depth: usize) {
         self.locals.insert(id.0, depth);
     }
 
-    fn look_up_variable(
-        &mut self,
-        interner: &Interner,
-        name: &Token,
-        id: ExprId,
-    ) -> Result<Value, ErrCause> {
-        let distance = self.locals.get(id.0);
-        if let Some(distance) = distance {
-            Ok(Environment::get_at(
-                &self.environment,
-                *distance,
-                name.lexeme,
-            ))
+    fn look_up_variable(
+        &mut self,
+        interner: &Interner,
+        name: &Token,
+        id: ExprId,
+    ) -> Result<Value, ErrCause> {
+        let distance = self.locals.get(id.0);
+        if let Some(distance) = distance {
+            // A hoisted local function can be referenced before its declaration has run.
+            Environment::get_at(&self.environment, *distance, name.lexeme).ok_or_else(|| {
+                ErrCause::Error(
+                    name.clone(),
+                    format!("Undefined variable '{}'.", interner.resolve(name.lexeme)),
+                )
+            })
+        } else {
+            self.global_environment.get(interner, name)
+        }
+    }
+
+    // Counts a loop iteration or call against the step budget.
+    fn step(&mut self, token: &Token) -> Result<(), ErrCause> {
+        match &mut self.step_budget {
+            Some(0) => Err(ErrCause::Limit(
+                token.clone(),
+                String::from("Step budget exceeded."),
+            )),
+            Some(steps) => {
+                *steps -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn check_io(&self, token: &Token) -> Result<(), ErrCause> {
+        if self.io_disabled {
+            Err(ErrCause::Error(
+                token.clone(),
+                String::from("IO is disabled."),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Wraps a string built at runtime, unless it is longer than the configured limit.
+    fn new_string(&self, token: &Token, string: String) -> Result<Value, ErrCause> {
+        if self
+            .max_string_len
+            .is_some_and(|limit| string.len() > limit)
+        {
+            return Err(ErrCause::Limit(
+                token.clone(),
+                String::from("String length limit exceeded."),
+            ));
+        }
+        Ok(Value::String(Rc::new(string)))
+    }
+
+    fn check_condition(&self, token: &Token, condition: &Value) -> Result<bool, ErrCause> {
+        match condition {
+            Value::Bool(bool) => Ok(*bool),
+            _ if self.strict_bool => Err(ErrCause::Error(
+                token.clone(),
+                String::from("Condition must be a boolean."),
+            )),
+            _ => Ok(is_truthy(condition)),
+        }
+    }
+
+    // Adds an entry to a map, or replaces the value of an existing key in its place. Maps
+    // stay sorted by key if sorted_maps is set, and keep the order keys were added otherwise.
+    fn insert_entry(&self, map: &mut LoxMap, key: MapKey, value: Value) {
+        if self.sorted_maps {
+            map.insert_sorted(key, value);
         } else {
-            self.global_environment.get(interner, name)
+            map.insert(key, value);
         }
     }
 
@@ -485,6 +1840,19 @@ pub struct Resolver<'a> {
     scopes: Vec<FxHashMap<Symbol, bool>>,
     current_function: FunctionType,
     current_class: ClassType,
+    // Loops around the statement being resolved, within the current function.
+    loop_depth: usize,
+    // Scopes around the top level, which is a scope of its own in modules.
+    top_level_scopes: usize,
+    // Method names of the classes declared so far, including inherited ones, so that --lint
+    // can spot overrides. Classes are only known by name.
+    class_methods: FxHashMap<Symbol, Vec<Symbol>>,
+    // Methods called through 'super' in the method being resolved.
+    super_calls: Vec<Symbol>,
+    // Variables declared without an initializer and not assigned yet, as their scope depth
+    // (0 for globals) and name, so that --warn-uninit can spot reads of them. Assignments
+    // count in textual order, whatever the control flow.
+    uninitialized: FxHashSet<(usize, Symbol)>,
 }
 
 impl Resolver<'_> {
@@ -495,6 +1863,11 @@ impl Resolver<'_> {
             scopes: Vec::new(),
             current_function: FunctionType::None,
             current_class: ClassType::None,
+            loop_depth: 0,
+            top_level_scopes: 0,
+            class_methods: FxHashMap::default(),
+            super_calls: Vec::new(),
+            uninitialized: FxHashSet::default(),
         }
     }
 
@@ -504,31 +1877,96 @@ impl Resolver<'_> {
         }
     }
 
+    // Resolves a module, whose top-level declarations are local to it.
+    pub fn resolve_module(&mut self, statements: &[Stmt]) {
+        self.top_level_scopes = 1;
+        self.begin_scope();
+        self.resolve_block(statements);
+        self.end_scope();
+    }
+
+    // Declares the block's functions up front, so that functions declared in the same block
+    // can refer to each other regardless of their order.
+    fn resolve_block(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            if let Stmt::Function(function) = stmt {
+                self.declare(&function.name);
+                self.define(&function.name);
+            }
+        }
+
+        self.resolve(statements);
+    }
+
     fn resolve_stmt(&mut self, statement: &Stmt) {
         match statement {
+            Stmt::Assert { condition, .. } => self.resolve_expr(condition),
             Stmt::Block { statements } => {
                 self.begin_scope();
-                self.resolve(statements);
+                self.resolve_block(statements);
                 self.end_scope();
             }
+            Stmt::Enum { name, members } => {
+                self.declare(name);
+                self.define(name);
+
+                for (i, member) in members.iter().enumerate() {
+                    if members[..i]
+                        .iter()
+                        .any(|other| other.lexeme == member.lexeme)
+                    {
+                        self.app.error_token(
+                            member,
+                            &format!(
+                                "Duplicate enum member '{}'.",
+                                self.app.interner.resolve(member.lexeme)
+                            ),
+                        );
+                    }
+                }
+            }
             Stmt::Expression(expr) => self.resolve_expr(expr),
             Stmt::Function(function) => {
-                self.declare(&function.name);
-                self.define(&function.name);
-
-                self.resolve_function(function, FunctionType::Function);
+                // The name was already declared by resolve_block, or is a global.
+                let function_type = if function.is_generator {
+                    FunctionType::Generator
+                } else {
+                    FunctionType::Function
+                };
+                self.resolve_function(function, function_type);
             }
             Stmt::Class {
                 name,
                 methods,
                 superclass,
+                mixins,
+                fields,
             } => {
                 let enclosing_class = self.current_class;
-                self.current_class = ClassType::Class;
 
                 self.declare(name);
                 self.define(name);
 
+                // Initializers run before the instance is set up, so they can't use 'this'.
+                self.current_class = ClassType::FieldInitializer;
+                for (_, initializer) in fields {
+                    if let Some(initializer) = initializer {
+                        self.resolve_expr(initializer);
+                    }
+                }
+                self.current_class = ClassType::Class;
+
+                for mixin in mixins {
+                    if let Expr::Variable(_, ExprVariable { name: mixin_name }) = mixin {
+                        if mixin_name.lexeme == name.lexeme {
+                            self.app
+                                .error_token(mixin_name, "A class can't mix in itself.");
+                        }
+                    }
+                    self.resolve_expr(mixin);
+                }
+
+                let mut inherited_methods = Vec::new();
                 if let Some(superclass) = superclass {
                     if let Expr::Variable(
                         _,
@@ -541,6 +1979,9 @@ impl Resolver<'_> {
                             self.app
                                 .error_token(name, "A class can't inherit from itself.");
                         }
+                        if let Some(methods) = self.class_methods.get(&superclass_name.lexeme) {
+                            inherited_methods = methods.clone();
+                        }
                     } else {
                         unreachable!();
                     }
@@ -558,15 +1999,59 @@ impl Resolver<'_> {
                 let last = self.scopes.len() - 1;
                 self.scopes[last].insert(self.app.interner.sym_this, true);
 
+                let members: Vec<&Token> = fields
+                    .iter()
+                    .map(|(name, _)| name)
+                    .chain(methods.iter().map(|method| &method.name))
+                    .collect();
+                for (i, member) in members.iter().enumerate() {
+                    if members[..i]
+                        .iter()
+                        .any(|other| other.lexeme == member.lexeme)
+                    {
+                        self.app.error_token(
+                            member,
+                            &format!(
+                                "Duplicate class member '{}'.",
+                                self.app.interner.resolve(member.lexeme)
+                            ),
+                        );
+                    }
+                }
+
                 for method in methods {
                     let declaration = if method.name.lexeme == self.app.interner.sym_init {
                         FunctionType::Initializer
                     } else {
                         FunctionType::Method
                     };
+                    let enclosing_super_calls = mem::take(&mut self.super_calls);
                     self.resolve_function(method, declaration);
+                    let super_calls = mem::replace(&mut self.super_calls, enclosing_super_calls);
+
+                    let name = method.name.lexeme;
+                    if self.app.lint
+                        && inherited_methods.contains(&name)
+                        && !super_calls.contains(&name)
+                    {
+                        let name = self.app.interner.resolve(name);
+                        self.app.warning_token(
+                            &method.name,
+                            &format!(
+                                "Method '{}' overrides an inherited method without calling 'super.{}'.",
+                                name, name
+                            ),
+                        );
+                    }
                 }
 
+                for method in methods {
+                    if !inherited_methods.contains(&method.name.lexeme) {
+                        inherited_methods.push(method.name.lexeme);
+                    }
+                }
+                self.class_methods.insert(name.lexeme, inherited_methods);
+
                 self.end_scope();
 
                 if superclass.is_some() {
@@ -579,6 +2064,7 @@ impl Resolver<'_> {
                 condition,
                 then_branch,
                 else_branch,
+                ..
             } => {
                 self.resolve_expr(condition);
                 self.resolve_stmt(then_branch);
@@ -586,7 +2072,18 @@ impl Resolver<'_> {
                     self.resolve_stmt(stmt)
                 }
             }
-            Stmt::Print { expression } => self.resolve_expr(expression),
+            Stmt::Import { keyword, name, .. } => {
+                if self.scopes.len() > self.top_level_scopes {
+                    self.app
+                        .error_token(keyword, "Can only import at top level.");
+                }
+
+                if let Some(name) = name {
+                    self.declare(name);
+                    self.define(name);
+                }
+            }
+            Stmt::Print { expression, .. } => self.resolve_expr(expression),
             Stmt::Return { keyword, value } => {
                 if self.current_function == FunctionType::None {
                     self.app
@@ -599,36 +2096,117 @@ impl Resolver<'_> {
                             .error_token(keyword, "Can't return a value from an initializer.")
                     }
 
+                    if self.current_function == FunctionType::Generator {
+                        self.app
+                            .error_token(keyword, "Can't return a value from a generator.")
+                    }
+
                     self.resolve_expr(expr)
                 }
             }
+            Stmt::Break { keyword } => {
+                if self.loop_depth == 0 {
+                    self.app
+                        .error_token(keyword, "Can't use 'break' outside of a loop.");
+                }
+            }
+            Stmt::Continue { keyword } => {
+                if self.loop_depth == 0 {
+                    self.app
+                        .error_token(keyword, "Can't use 'continue' outside of a loop.");
+                }
+            }
+            Stmt::Throw { value, .. } => self.resolve_expr(value),
+            Stmt::Try {
+                body,
+                catch_var,
+                catch_body,
+            } => {
+                self.begin_scope();
+                self.resolve_block(body);
+                self.end_scope();
+
+                self.begin_scope();
+                self.declare(catch_var);
+                self.define(catch_var);
+                self.resolve_block(catch_body);
+                self.end_scope();
+            }
             Stmt::Var { name, initializer } => {
                 self.declare(name);
                 if let Some(initializer) = initializer {
                     self.resolve_expr(initializer);
+                } else if self.app.warn_uninit {
+                    self.uninitialized.insert((self.scopes.len(), name.lexeme));
                 }
                 self.define(name);
             }
-            Stmt::While { condition, body } => {
+            Stmt::VarList { declarations } => self.resolve(declarations),
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
                 self.resolve_expr(condition);
-                self.resolve_stmt(body);
+                self.resolve_loop_body(body);
+                for expr in increment {
+                    self.resolve_expr(expr);
+                }
+            }
+            Stmt::Repeat { count, body, .. } => {
+                self.resolve_expr(count);
+                self.resolve_loop_body(body);
+            }
+            Stmt::Foreach {
+                name,
+                iterable,
+                body,
+                ..
+            } => {
+                self.resolve_expr(iterable);
+
+                self.begin_scope();
+                self.declare(name);
+                self.define(name);
+                self.resolve_loop_body(body);
+                self.end_scope();
+            }
+            Stmt::Yield { keyword, value } => {
+                if self.current_function != FunctionType::Generator {
+                    self.app
+                        .error_token(keyword, "Can't yield outside a generator function.");
+                }
+
+                self.resolve_expr(value);
             }
         }
     }
 
+    fn resolve_loop_body(&mut self, body: &Stmt) {
+        self.loop_depth += 1;
+        self.resolve_stmt(body);
+        self.loop_depth -= 1;
+    }
+
     fn resolve_function(&mut self, stmt_function: &StmtFunction, function_type: FunctionType) {
+        // current_class is deliberately left untouched, so functions nested in a method can
+        // still use 'this' from the enclosing method's scope. Loops around the function don't
+        // surround its body.
         let enclosing_function = self.current_function;
         self.current_function = function_type;
+        let enclosing_loop_depth = mem::take(&mut self.loop_depth);
 
         self.begin_scope();
         for param in &stmt_function.params {
             self.declare(param);
             self.define(param);
         }
-        self.resolve(&stmt_function.body);
+        self.resolve_block(&stmt_function.body);
         self.end_scope();
 
         self.current_function = enclosing_function;
+        self.loop_depth = enclosing_loop_depth;
     }
 
     fn resolve_expr(&mut self, expr: &Expr) {
@@ -636,6 +2214,8 @@ impl Resolver<'_> {
             Expr::Assign { name, value, id } => {
                 self.resolve_expr(value);
                 self.resolve_local(*id, name);
+                let depth = self.scope_depth(name);
+                self.uninitialized.remove(&(depth, name.lexeme));
             }
             Expr::Binary { left, right, .. } => {
                 self.resolve_expr(left);
@@ -650,13 +2230,73 @@ impl Resolver<'_> {
                 }
             }
             Expr::Grouping { expression } => self.resolve_expr(expression),
+            Expr::List { elements } => {
+                for element in elements {
+                    self.resolve_expr(element);
+                }
+            }
+            Expr::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.resolve_expr(key);
+                    self.resolve_expr(value);
+                }
+            }
+            Expr::Index { object, index, .. } => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+            }
+            Expr::IndexSet {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+                self.resolve_expr(value);
+            }
             Expr::Literal { .. } => {}
             Expr::Logical { left, right, .. } => {
                 self.resolve_expr(left);
                 self.resolve_expr(right);
             }
+            Expr::Ternary {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.resolve_expr(condition);
+                self.resolve_expr(then_branch);
+                self.resolve_expr(else_branch);
+            }
+            Expr::Match {
+                subject,
+                arms,
+                default,
+                ..
+            } => {
+                self.resolve_expr(subject);
+                for (value, result) in arms {
+                    self.resolve_expr(value);
+                    self.resolve_expr(result);
+                }
+                if let Some(default) = default {
+                    self.resolve_expr(default);
+                }
+            }
             Expr::Unary { right, .. } => self.resolve_expr(right),
+            Expr::TypeOf { operand, .. } => self.resolve_expr(operand),
+            // Left unresolved, so that the interpreter looks the name up in the globals.
+            Expr::Global { value, .. } => {
+                if let Some(value) = value {
+                    self.resolve_expr(value);
+                }
+            }
             Expr::Variable(id, ExprVariable { name }) => {
+                // Only the innermost scope can hold a variable whose initializer is being
+                // resolved, so declarations in enclosing scopes are never reported here. A
+                // shadowing `var a = a;` still is, since the new 'a' is already declared.
                 if let Some(scope) = self.scopes.last() {
                     if let Some(defined) = scope.get(&name.lexeme) {
                         if !defined {
@@ -668,6 +2308,18 @@ impl Resolver<'_> {
                     }
                 }
                 self.resolve_local(*id, name);
+                if self
+                    .uninitialized
+                    .remove(&(self.scope_depth(name), name.lexeme))
+                {
+                    self.app.warning_token(
+                        name,
+                        &format!(
+                            "Variable '{}' is read before it is assigned.",
+                            self.app.interner.resolve(name.lexeme)
+                        ),
+                    );
+                }
             }
             Expr::Get { object, .. } => self.resolve_expr(object),
             Expr::Set { object, value, .. } => {
@@ -678,14 +2330,25 @@ impl Resolver<'_> {
                 if self.current_class == ClassType::None {
                     self.app
                         .error_token(keyword, "Can't use 'this' outside of a class.");
+                } else if self.current_class == ClassType::FieldInitializer {
+                    self.app
+                        .error_token(keyword, "Can't use 'this' in a field initializer.");
                 } else {
                     self.resolve_local(*id, keyword);
                 }
             }
-            Expr::Super { keyword, id, .. } => {
+            Expr::Super {
+                keyword,
+                method,
+                id,
+            } => {
+                self.super_calls.push(method.lexeme);
                 if self.current_class == ClassType::None {
                     self.app
                         .error_token(keyword, "Can't use 'super' outside of a class.");
+                } else if self.current_class == ClassType::FieldInitializer {
+                    self.app
+                        .error_token(keyword, "Can't use 'super' in a field initializer.");
                 } else if self.current_class != ClassType::Subclass {
                     self.app
                         .error_token(keyword, "Can't use 'super' in a class with no superclass.");
@@ -704,6 +2367,14 @@ impl Resolver<'_> {
         }
     }
 
+    // Depth of the scope a variable resolves to, counting the globals as 0.
+    fn scope_depth(&self, name: &Token) -> usize {
+        self.scopes
+            .iter()
+            .rposition(|scope| scope.contains_key(&name.lexeme))
+            .map_or(0, |i| i + 1)
+    }
+
     fn declare(&mut self, name: &Token) {
         if let Some(scope) = self.scopes.last_mut() {
             if scope.contains_key(&name.lexeme) {
@@ -725,6 +2396,8 @@ impl Resolver<'_> {
     }
 
     fn end_scope(&mut self) {
+        let depth = self.scopes.len();
+        self.uninitialized.retain(|(scope, _)| *scope != depth);
         self.scopes.pop();
     }
 }
@@ -737,10 +2410,200 @@ enum Value {
     Bool(bool),
     Callable(Rc<Function>),
     Instance(Rc<Instance>),
+    Generator(Rc<Generator>),
+    Module(Rc<Module>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<LoxMap>>),
+    Nil,
+}
+
+// The entries of a map, in the order their keys were added.
+type LoxMap = IndexMap<MapKey, Value, BuildHasherDefault<FxHasher>>;
+
+// A key of a map. Strings are compared by their text whether or not they were built at
+// runtime, and numbers are compared by value, so 0 and -0 are the same key. String keys own
+// their text instead of interning it, so it is freed along with the map.
+#[derive(Clone, PartialEq, Eq)]
+enum MapKey {
+    // The bits of a number that isn't NaN, with -0 stored as 0.
+    Number(u64),
+    String(Rc<String>),
+}
+
+// A borrowed map key, for looking up interned strings without copying them out of the
+// interner. It hashes like the MapKey it stands for.
+#[derive(PartialEq, Hash)]
+enum KeyRef<'a> {
+    Number(u64),
+    String(&'a str),
+}
+
+impl Hash for MapKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.borrowed().hash(state);
+    }
+}
+
+// The canonical order of keys: numbers in ascending order, then strings by their text.
+impl Ord for MapKey {
+    fn cmp(&self, other: &MapKey) -> Ordering {
+        match (self, other) {
+            (MapKey::Number(left), MapKey::Number(right)) => {
+                f64::from_bits(*left).total_cmp(&f64::from_bits(*right))
+            }
+            (MapKey::Number(_), MapKey::String(_)) => Ordering::Less,
+            (MapKey::String(_), MapKey::Number(_)) => Ordering::Greater,
+            (MapKey::String(left), MapKey::String(right)) => left.cmp(right),
+        }
+    }
+}
+
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &MapKey) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Equivalent<MapKey> for KeyRef<'_> {
+    fn equivalent(&self, key: &MapKey) -> bool {
+        *self == key.borrowed()
+    }
+}
+
+impl MapKey {
+    // The key a value is stored under.
+    fn new(interner: &Interner, token: &Token, value: &Value) -> Result<MapKey, ErrCause> {
+        match value {
+            Value::String(string) => Ok(MapKey::String(Rc::clone(string))),
+            Value::InternedString(sym) => Ok(MapKey::String(Rc::new(interner.resolve(*sym)))),
+            _ => MapKey::number(token, value),
+        }
+    }
+
+    // Like new, but lends the key to `f` for looking it up instead of building it.
+    fn find<R>(
+        interner: &Interner,
+        token: &Token,
+        value: &Value,
+        f: impl FnOnce(KeyRef) -> R,
+    ) -> Result<R, ErrCause> {
+        match value {
+            Value::String(string) => Ok(f(KeyRef::String(string))),
+            Value::InternedString(sym) => {
+                Ok(interner.resolve_with(*sym, |string| f(KeyRef::String(string))))
+            }
+            _ => Ok(f(MapKey::number(token, value)?.borrowed())),
+        }
+    }
+
+    fn number(token: &Token, value: &Value) -> Result<MapKey, ErrCause> {
+        match value {
+            // NaN isn't equal to itself, so it could never be looked up again.
+            Value::Number(num) if num.is_nan() => Err(ErrCause::Error(
+                token.clone(),
+                String::from("Map key can't be NaN."),
+            )),
+            // Adding 0 turns -0 into 0.
+            Value::Number(num) => Ok(MapKey::Number((num + 0.0).to_bits())),
+            _ => Err(ErrCause::Error(
+                token.clone(),
+                String::from("Map keys must be strings or numbers."),
+            )),
+        }
+    }
+
+    fn borrowed(&self) -> KeyRef<'_> {
+        match self {
+            MapKey::Number(num) => KeyRef::Number(*num),
+            MapKey::String(string) => KeyRef::String(string),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            MapKey::Number(num) => Value::Number(f64::from_bits(*num)),
+            MapKey::String(string) => Value::String(Rc::clone(string)),
+        }
+    }
+}
+
+// Values a host program can hand to the interpreter.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoxValue {
     Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+impl LoxValue {
+    fn from_value(interner: &Interner, value: &Value, precision: Option<usize>) -> LoxValue {
+        match value {
+            Value::Nil => LoxValue::Nil,
+            Value::Bool(b) => LoxValue::Bool(*b),
+            Value::Number(num) => LoxValue::Number(*num),
+            _ => LoxValue::String(stringify(interner, value, precision)),
+        }
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            LoxValue::Nil => Value::Nil,
+            LoxValue::Bool(b) => Value::Bool(b),
+            LoxValue::Number(num) => Value::Number(num),
+            LoxValue::String(str) => Value::String(Rc::new(str)),
+        }
+    }
+}
+
+impl From<bool> for LoxValue {
+    fn from(b: bool) -> Self {
+        LoxValue::Bool(b)
+    }
+}
+
+impl From<f64> for LoxValue {
+    fn from(num: f64) -> Self {
+        LoxValue::Number(num)
+    }
+}
+
+impl From<&str> for LoxValue {
+    fn from(str: &str) -> Self {
+        LoxValue::String(String::from(str))
+    }
+}
+
+impl From<String> for LoxValue {
+    fn from(str: String) -> Self {
+        LoxValue::String(str)
+    }
 }
 
 impl Value {
+    // Lends the text of a string to `f`, whether it is interned or owned. Returns None for
+    // values that aren't strings.
+    fn with_str<R>(&self, interner: &Interner, f: impl FnOnce(&str) -> R) -> Option<R> {
+        match self {
+            Value::String(string) => Some(f(string)),
+            Value::InternedString(sym) => Some(interner.resolve_with(*sym, f)),
+            _ => None,
+        }
+    }
+
+    // Like with_str, for two strings at once.
+    fn with_strs<R>(
+        &self,
+        other: &Value,
+        interner: &Interner,
+        f: impl FnOnce(&str, &str) -> R,
+    ) -> Option<R> {
+        self.with_str(interner, |left| {
+            other.with_str(interner, |right| f(left, right))
+        })
+        .flatten()
+    }
+
     fn to_instance(&self) -> Option<Rc<Instance>> {
         match self {
             Value::Instance(instance) => Some(Rc::clone(instance)),
@@ -750,7 +2613,10 @@ impl Value {
 
     fn is_class(&self) -> bool {
         if let Value::Callable(function) = self {
-            matches!(Rc::borrow(function), Function::Class(..))
+            matches!(
+                Rc::borrow(function),
+                Function::Class(..) | Function::Enum(..)
+            )
         } else {
             false
         }
@@ -759,7 +2625,7 @@ impl Value {
     fn to_class(&self) -> Option<Rc<Class>> {
         match self {
             Value::Callable(function) => {
-                if let Function::Class(_, class) = Rc::borrow(function) {
+                if let Function::Class(_, class) | Function::Enum(class, _) = Rc::borrow(function) {
                     Some(Rc::clone(class))
                 } else {
                     None
@@ -768,15 +2634,34 @@ impl Value {
             _ => None,
         }
     }
+
+    fn enum_members(&self) -> Option<&FxHashMap<Symbol, Value>> {
+        match self {
+            Value::Callable(function) => match Rc::borrow(function) {
+                Function::Enum(_, members) => Some(members),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
 }
 
+type NativeFunction = fn(&mut Interpreter, &Interner, &Token, &[Value]) -> Result<Value, ErrCause>;
+
 enum Function {
-    Native(
-        usize,
-        fn(&mut Interpreter, &[Value]) -> Result<Value, ErrCause>,
-    ),
+    // Takes from the first to the second number of arguments. Optional arguments that are left
+    // out are passed as nil.
+    Native(usize, usize, NativeFunction),
     Declared(Rc<StmtFunction>, Rc<Environment>, bool),
+    // A method stored in a field of the instance it is bound to. Holding that instance
+    // weakly avoids a reference cycle; reading the field binds it strongly again.
+    WeakBound(Rc<StmtFunction>, Rc<Environment>, bool, Weak<Instance>),
     Class(usize, Rc<Class>),
+    // An enum and its members. The members refer to the class, so they are kept here rather
+    // than in it, which would make them keep each other alive.
+    Enum(Rc<Class>, FxHashMap<Symbol, Value>),
+    // A method read from its class, called with the instance as its first argument.
+    Unbound(Rc<Class>, Rc<Function>),
 }
 
 impl Function {
@@ -784,74 +2669,212 @@ impl Function {
         &self,
         interpreter: &mut Interpreter,
         interner: &Interner,
+        paren: &Token,
         arguments: &[Value],
     ) -> Result<Value, ErrCause> {
         match self {
-            Function::Native(_, function) => function(interpreter, arguments),
+            Function::Native(_, arity, function) if arguments.len() < *arity => {
+                let mut arguments = arguments.to_vec();
+                arguments.resize(*arity, Value::Nil);
+                function(interpreter, interner, paren, &arguments)
+            }
+            Function::Native(.., function) => function(interpreter, interner, paren, arguments),
             Function::Declared(stmt_function, closure, is_initializer) => {
-                let StmtFunction { params, body, .. } = Rc::borrow(stmt_function);
+                let StmtFunction {
+                    params,
+                    body,
+                    is_generator,
+                    ..
+                } = Rc::borrow(stmt_function);
 
-                let environment = Environment::new(Some(Rc::clone(closure)));
+                let environment = interpreter.new_environment(Rc::clone(closure));
 
                 for i in 0..params.len() {
                     environment.define(params[i].lexeme, arguments[i].clone())
                 }
 
+                // Generator bodies only start running when the first value is asked for.
+                if *is_generator {
+                    return Ok(Value::Generator(Rc::new(Generator::new(
+                        Rc::clone(stmt_function),
+                        environment,
+                    ))));
+                }
+
                 let result = interpreter.execute_block(interner, body, environment);
 
                 if *is_initializer {
-                    return Ok(closure
+                    if let Err(
+                        ErrCause::Error(..)
+                        | ErrCause::Limit(..)
+                        | ErrCause::AssertionFailure(..)
+                        | ErrCause::Thrown(..),
+                    ) = result
+                    {
+                        return result.map(|_| Value::Nil);
+                    }
+
+                    // Methods are bound before they are called, so 'this' is always there;
+                    // fail cleanly rather than panic should that ever change.
+                    return closure
                         .values
                         .borrow()
                         .get(&interner.sym_this)
-                        .unwrap()
-                        .clone());
+                        .cloned()
+                        .ok_or_else(|| {
+                            ErrCause::Error(
+                                paren.clone(),
+                                String::from("Initializer called without an instance."),
+                            )
+                        });
                 }
 
                 result.map(|_| Value::Nil)
             }
+            Function::WeakBound(stmt_function, closure, is_initializer, instance) => {
+                match instance.upgrade() {
+                    Some(instance) => Function::Declared(
+                        Rc::clone(stmt_function),
+                        Rc::clone(closure),
+                        *is_initializer,
+                    )
+                    .bind(interner, instance)
+                    .call(interpreter, interner, paren, arguments),
+                    None => Err(ErrCause::Error(
+                        paren.clone(),
+                        String::from("Instance bound to this method no longer exists."),
+                    )),
+                }
+            }
+            Function::Enum(class, _) => Err(ErrCause::Error(
+                paren.clone(),
+                format!("Can't instantiate enum '{}'.", interner.resolve(class.name)),
+            )),
             Function::Class(_, class) => {
                 let instance = Rc::new(Instance::new(Rc::clone(class)));
+                interpreter.initialize_fields(interner, class, &instance)?;
                 if let Some(Value::Callable(initializer)) = instance.find_method(interner.sym_init)
                 {
                     initializer.bind(interner, Rc::clone(&instance)).call(
                         interpreter,
                         interner,
+                        paren,
                         arguments,
                     )?;
                 }
 
                 Ok(Value::Instance(instance))
             }
+            Function::Unbound(class, method) => match &arguments[0] {
+                Value::Instance(instance) if instance.class.inherits_from(class) => method
+                    .bind(interner, Rc::clone(instance))
+                    .call(interpreter, interner, paren, &arguments[1..]),
+                _ => Err(ErrCause::Error(
+                    paren.clone(),
+                    format!(
+                        "Expected a '{}' instance as the receiver.",
+                        interner.resolve(class.name)
+                    ),
+                )),
+            },
+        }
+    }
+
+    fn bind(&self, interner: &Interner, instance: Rc<Instance>) -> Function {
+        if let Function::Declared(stmt_function, closure, is_initializer) = self {
+            let environment = Environment::new(Some(Rc::clone(closure)));
+            environment.define(interner.sym_this, Value::Instance(instance));
+            Function::Declared(
+                Rc::clone(stmt_function),
+                Rc::new(environment),
+                *is_initializer,
+            )
+        } else {
+            unreachable!()
+        }
+    }
+
+    // Bound methods carry 'this' in their closure; constructing an instance counts as a
+    // method call too.
+    fn is_method(&self, interner: &Interner) -> bool {
+        match self {
+            Function::Native(..) => false,
+            Function::Declared(_, closure, _) => {
+                closure.values.borrow().contains_key(&interner.sym_this)
+            }
+            Function::WeakBound(..)
+            | Function::Class(..)
+            | Function::Enum(..)
+            | Function::Unbound(..) => true,
+        }
+    }
+
+    // Names the function for error messages.
+    fn describe(&self, interner: &Interner) -> String {
+        match self {
+            Function::Native(..) => String::from("native fn"),
+            Function::Declared(stmt_function, ..) | Function::WeakBound(stmt_function, ..) => {
+                format!("'{}'", interner.resolve(stmt_function.name.lexeme))
+            }
+            Function::Class(_, class) | Function::Enum(class, _) => {
+                format!("'{}'", interner.resolve(class.name))
+            }
+            Function::Unbound(_, method) => method.describe(interner),
         }
     }
 
-    fn bind(&self, interner: &Interner, instance: Rc<Instance>) -> Function {
-        if let Function::Declared(stmt_function, closure, is_initializer) = self {
-            let environment = Environment::new(Some(Rc::clone(closure)));
-            environment.define(interner.sym_this, Value::Instance(instance));
-            Function::Declared(
-                Rc::clone(stmt_function),
-                Rc::new(environment),
-                *is_initializer,
-            )
-        } else {
-            unreachable!()
+    // Names the function's frames in profiles.
+    fn profile_name(&self, interner: &Interner) -> Symbol {
+        match self {
+            Function::Native(..) => interner.get_or_intern("<native fn>"),
+            Function::Declared(stmt_function, ..) | Function::WeakBound(stmt_function, ..) => {
+                stmt_function.name.lexeme
+            }
+            Function::Class(_, class) | Function::Enum(class, _) => class.name,
+            Function::Unbound(_, method) => method.profile_name(interner),
         }
     }
 
+    // The number of arguments the function takes, including optional ones.
     fn arity(&self) -> usize {
         match self {
-            Function::Native(arity, _) => *arity,
+            Function::Native(_, arity, _) => *arity,
             Function::Declared(stmt_function, _, _) => stmt_function.params.len(),
+            Function::WeakBound(stmt_function, ..) => stmt_function.params.len(),
             Function::Class(arity, _) => *arity,
+            Function::Enum(..) => 0,
+            Function::Unbound(_, method) => method.arity() + 1,
+        }
+    }
+
+    // The number of arguments that can't be left out.
+    fn required_arity(&self) -> usize {
+        match self {
+            Function::Native(required, ..) => *required,
+            _ => self.arity(),
         }
     }
 }
 
 enum ErrCause {
     Error(Token, String),
+    // A runtime error from exceeding a limit set on the interpreter. Unlike other errors,
+    // it can't be caught, so that a script can't keep running past its limits.
+    Limit(Token, String),
+    AssertionFailure(Token, String),
+    Thrown(Token, Value),
     Return(Value),
+    Break,
+    Continue,
+}
+
+// Handles the result of running a loop body, returning false if the loop should end.
+fn loop_control(result: Result<(), ErrCause>) -> Result<bool, ErrCause> {
+    match result {
+        Ok(()) | Err(ErrCause::Continue) => Ok(true),
+        Err(ErrCause::Break) => Ok(false),
+        Err(err) => Err(err),
+    }
 }
 
 fn is_truthy(value: &Value) -> bool {
@@ -862,26 +2885,595 @@ fn is_truthy(value: &Value) -> bool {
     }
 }
 
+// The token to report a bad superclass or mixin at: its name, or the name of the class being
+// declared if the clause isn't a plain variable.
+fn class_clause_token<'a>(class_name: &'a Token, clause: &'a Expr) -> &'a Token {
+    match clause {
+        Expr::Variable(_, variable) => &variable.name,
+        _ => class_name,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::String(_) | Value::InternedString(_) => "string",
+        Value::Number(_) => "number",
+        Value::Bool(_) => "boolean",
+        Value::Callable(function) => match **function {
+            Function::Class(..) | Function::Enum(..) => "class",
+            _ => "function",
+        },
+        Value::Instance(_) => "instance",
+        Value::Generator(_) => "generator",
+        Value::Module(_) => "module",
+        Value::List(_) => "list",
+        Value::Map(_) => "map",
+        Value::Nil => "nil",
+    }
+}
+
+// Joins two strings with a single allocation.
+fn concat(left: &str, right: &str) -> String {
+    let mut string = String::with_capacity(left.len() + right.len());
+    string.push_str(left);
+    string.push_str(right);
+    string
+}
+
 fn is_equal(left: &Value, right: &Value, interner: &Interner) -> bool {
     match (left, right) {
-        (Value::String(l), Value::String(r)) => Rc::ptr_eq(l, r),
-        (Value::InternedString(l), Value::String(r)) => interner.resolve(*l) == **r,
-        (Value::String(l), Value::InternedString(r)) => **l == interner.resolve(*r),
+        // Identical strings are equal without comparing their text.
+        (Value::String(l), Value::String(r)) if Rc::ptr_eq(l, r) => true,
         (Value::InternedString(l), Value::InternedString(r)) => l == r,
+        (Value::String(_) | Value::InternedString(_), _) => left
+            .with_strs(right, interner, |l, r| l == r)
+            .unwrap_or(false),
         (Value::Number(l), Value::Number(r)) => l == r,
         (Value::Bool(l), Value::Bool(r)) => l == r,
         (Value::Nil, Value::Nil) => true,
         (Value::Instance(l), Value::Instance(r)) => Rc::ptr_eq(l, r),
         (Value::Callable(l), Value::Callable(r)) => Rc::ptr_eq(l, r),
+        (Value::Generator(l), Value::Generator(r)) => Rc::ptr_eq(l, r),
+        (Value::Module(l), Value::Module(r)) => Rc::ptr_eq(l, r),
+        // Lists and maps can change after they are compared, so like instances they are
+        // only equal to themselves, not to another collection with the same contents.
+        (Value::List(l), Value::List(r)) => Rc::ptr_eq(l, r),
+        (Value::Map(l), Value::Map(r)) => Rc::ptr_eq(l, r),
         (_, _) => false,
     }
 }
 
-fn stringify(interner: &Interner, value: &Value) -> String {
+fn native_benchmark(
+    interpreter: &mut Interpreter,
+    interner: &Interner,
+    paren: &Token,
+    arguments: &[Value],
+) -> Result<Value, ErrCause> {
+    interpreter.check_io(paren)?;
+    let name = match &arguments[0] {
+        Value::String(_) | Value::InternedString(_) => {
+            stringify(interner, &arguments[0], interpreter.precision)
+        }
+        _ => {
+            return Err(ErrCause::Error(
+                paren.clone(),
+                String::from("Benchmark name must be a string."),
+            ))
+        }
+    };
+
+    let iterations = match arguments[1] {
+        Value::Number(num) if num >= 1.0 && num.fract() == 0.0 => num as u64,
+        _ => {
+            return Err(ErrCause::Error(
+                paren.clone(),
+                String::from("Benchmark iterations must be a positive integer."),
+            ))
+        }
+    };
+
+    let function = match &arguments[2] {
+        Value::Callable(function) if function.arity() == 0 => Rc::clone(function),
+        _ => {
+            return Err(ErrCause::Error(
+                paren.clone(),
+                String::from("Benchmark function must be callable without arguments."),
+            ))
+        }
+    };
+
+    let start = time::Instant::now();
+    for _ in 0..iterations {
+        interpreter.call(interner, paren, &function, &[])?;
+    }
+    let total = start.elapsed().as_secs_f64();
+    let average = total / iterations as f64;
+
+    println!(
+        "{}: {} iterations in {:.6} s ({:.6} s per iteration)",
+        name, iterations, total, average
+    );
+
+    Ok(Value::Number(average))
+}
+
+// Checks the arguments of a native taking a list and a function of `arity` arguments to call
+// on its elements. Returns the elements the list has now, so that the function changing the
+// list doesn't affect which elements it is called with.
+fn list_and_function(
+    native: &str,
+    paren: &Token,
+    arguments: &[Value],
+    arity: usize,
+) -> Result<(Vec<Value>, Rc<Function>), ErrCause> {
+    let elements = match &arguments[0] {
+        Value::List(list) => RefCell::borrow(list).clone(),
+        _ => {
+            return Err(ErrCause::Error(
+                paren.clone(),
+                format!("First argument to {}() must be a list.", native),
+            ))
+        }
+    };
+
+    match &arguments[1] {
+        Value::Callable(function) if function.arity() == arity => {
+            Ok((elements, Rc::clone(function)))
+        }
+        _ => Err(ErrCause::Error(
+            paren.clone(),
+            format!(
+                "Second argument to {}() must be a function taking {} argument{}.",
+                native,
+                arity,
+                if arity == 1 { "" } else { "s" }
+            ),
+        )),
+    }
+}
+
+// A new list of what the function returns for each element.
+fn native_map(
+    interpreter: &mut Interpreter,
+    interner: &Interner,
+    paren: &Token,
+    arguments: &[Value],
+) -> Result<Value, ErrCause> {
+    let (elements, function) = list_and_function("map", paren, arguments, 1)?;
+    let mut mapped = Vec::with_capacity(elements.len());
+    for element in elements {
+        mapped.push(interpreter.call(interner, paren, &function, &[element])?);
+    }
+    Ok(Value::List(Rc::new(RefCell::new(mapped))))
+}
+
+// A new list of the elements the function returns a truthy value for.
+fn native_filter(
+    interpreter: &mut Interpreter,
+    interner: &Interner,
+    paren: &Token,
+    arguments: &[Value],
+) -> Result<Value, ErrCause> {
+    let (elements, function) = list_and_function("filter", paren, arguments, 1)?;
+    let mut kept = Vec::new();
+    for element in elements {
+        let keep = interpreter.call(interner, paren, &function, std::slice::from_ref(&element))?;
+        if interpreter.check_condition(paren, &keep)? {
+            kept.push(element);
+        }
+    }
+    Ok(Value::List(Rc::new(RefCell::new(kept))))
+}
+
+// Calls the function with the result so far, starting with `initial`, and each element in
+// turn, and returns its last result.
+fn native_reduce(
+    interpreter: &mut Interpreter,
+    interner: &Interner,
+    paren: &Token,
+    arguments: &[Value],
+) -> Result<Value, ErrCause> {
+    let (elements, function) = list_and_function("reduce", paren, arguments, 2)?;
+    let mut result = arguments[2].clone();
+    for element in elements {
+        result = interpreter.call(interner, paren, &function, &[result, element])?;
+    }
+    Ok(result)
+}
+
+// Sorts a list in place, stably. Without a comparator the list must hold only numbers or only
+// strings, which are sorted in ascending order. A comparator is called with two elements and
+// returns a negative number, zero or a positive number if the first belongs before, next to
+// or after the second.
+fn native_sort(
+    interpreter: &mut Interpreter,
+    interner: &Interner,
+    paren: &Token,
+    arguments: &[Value],
+) -> Result<Value, ErrCause> {
+    let Value::List(list) = &arguments[0] else {
+        return Err(ErrCause::Error(
+            paren.clone(),
+            String::from("First argument to sort() must be a list."),
+        ));
+    };
+
+    let sorted = if let Value::Nil = arguments[1] {
+        let elements = RefCell::borrow(list).clone();
+        let all_numbers = elements.iter().all(|e| matches!(e, Value::Number(_)));
+        let all_strings = elements
+            .iter()
+            .all(|e| matches!(e, Value::String(_) | Value::InternedString(_)));
+        if !all_numbers && !all_strings {
+            return Err(ErrCause::Error(
+                paren.clone(),
+                String::from("Can only sort lists of numbers or strings without a comparator."),
+            ));
+        }
+
+        merge_sort(elements, &mut |a, b| match (a, b) {
+            (Value::Number(a), Value::Number(b)) => Ok(a > b),
+            _ => Ok(a.with_strs(b, interner, |a, b| a > b).unwrap_or(false)),
+        })?
+    } else {
+        // The comparator sees the elements the list had when sort() was called.
+        let (elements, comparator) = list_and_function("sort", paren, arguments, 2)?;
+        merge_sort(elements, &mut |a, b| match interpreter.call(
+            interner,
+            paren,
+            &comparator,
+            &[a.clone(), b.clone()],
+        )? {
+            Value::Number(order) if !order.is_nan() => Ok(order > 0.0),
+            _ => Err(ErrCause::Error(
+                paren.clone(),
+                String::from("Comparator must return a number."),
+            )),
+        })?
+    };
+
+    *list.borrow_mut() = sorted;
+    Ok(Value::Nil)
+}
+
+// A stable merge sort that stops at the first error of `after`, which tells whether an element
+// belongs after another. Unlike slice::sort_by, it doesn't need the comparisons to be
+// consistent.
+fn merge_sort(
+    mut values: Vec<Value>,
+    after: &mut impl FnMut(&Value, &Value) -> Result<bool, ErrCause>,
+) -> Result<Vec<Value>, ErrCause> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+
+    let right = values.split_off(values.len() / 2);
+    let left = merge_sort(values, after)?;
+    let mut right = merge_sort(right, after)?.into_iter().peekable();
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    for element in left {
+        while let Some(next) = right.peek() {
+            if !after(&element, next)? {
+                break;
+            }
+            merged.extend(right.next());
+        }
+        merged.push(element);
+    }
+    merged.extend(right);
+
+    Ok(merged)
+}
+
+// A list with an element for each entry of a map, for keys(), values() and entries().
+fn map_elements(
+    paren: &Token,
+    native: &str,
+    map: &Value,
+    element: impl Fn(&MapKey, &Value) -> Value,
+) -> Result<Value, ErrCause> {
+    match map {
+        Value::Map(map) => {
+            let elements = RefCell::borrow(map)
+                .iter()
+                .map(|(key, value)| element(key, value))
+                .collect();
+            Ok(Value::List(Rc::new(RefCell::new(elements))))
+        }
+        _ => Err(ErrCause::Error(
+            paren.clone(),
+            format!("Argument to {}() must be a map.", native),
+        )),
+    }
+}
+
+fn native_set_precision(
+    interpreter: &mut Interpreter,
+    _: &Interner,
+    paren: &Token,
+    arguments: &[Value],
+) -> Result<Value, ErrCause> {
+    interpreter.precision = match arguments[0] {
+        Value::Number(num) if (1.0..=MAX_FORMAT_DIGITS).contains(&num) && num.fract() == 0.0 => {
+            Some(num as usize)
+        }
+        Value::Nil => None,
+        _ => {
+            return Err(ErrCause::Error(
+                paren.clone(),
+                String::from("Precision must be an integer between 1 and 100 or nil."),
+            ))
+        }
+    };
+
+    Ok(Value::Nil)
+}
+
+// Copies instances with their fields, lists with their elements and maps with their values
+// recursively. Everything else, including functions and classes, is shared. `path` holds the
+// instances, lists and maps being copied to detect cycles.
+fn deep_clone(paren: &Token, value: &Value, path: &mut Vec<*const ()>) -> Result<Value, ErrCause> {
+    let copy = match value {
+        // Enum members are constants compared by identity.
+        Value::Instance(instance) if !instance.class.is_enum => {
+            enter_clone(paren, Rc::as_ptr(instance) as *const (), path)?;
+            deep_clone_instance(paren, instance, path)?
+        }
+        Value::List(list) => {
+            enter_clone(paren, Rc::as_ptr(list) as *const (), path)?;
+            let elements = RefCell::borrow(list)
+                .iter()
+                .map(|element| deep_clone(paren, element, path))
+                .collect::<Result<Vec<Value>, ErrCause>>()?;
+            Value::List(Rc::new(RefCell::new(elements)))
+        }
+        Value::Map(map) => {
+            enter_clone(paren, Rc::as_ptr(map) as *const (), path)?;
+            let entries = RefCell::borrow(map)
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), deep_clone(paren, value, path)?)))
+                .collect::<Result<LoxMap, ErrCause>>()?;
+            Value::Map(Rc::new(RefCell::new(entries)))
+        }
+        _ => return Ok(value.clone()),
+    };
+
+    path.pop();
+    Ok(copy)
+}
+
+// Adds a value to the path of values being copied, unless it is already on it because it
+// contains itself.
+fn enter_clone(paren: &Token, ptr: *const (), path: &mut Vec<*const ()>) -> Result<(), ErrCause> {
+    if path.contains(&ptr) {
+        return Err(ErrCause::Error(
+            paren.clone(),
+            String::from("Cannot deep-clone cyclic structure."),
+        ));
+    }
+    path.push(ptr);
+    Ok(())
+}
+
+fn deep_clone_instance(
+    paren: &Token,
+    instance: &Rc<Instance>,
+    path: &mut Vec<*const ()>,
+) -> Result<Value, ErrCause> {
+    let copy = Rc::new(Instance::new(Rc::clone(&instance.class)));
+    for (name, field) in instance.fields.borrow().iter() {
+        let field = match field {
+            // A method stored on its own instance is bound to the copy instead.
+            Value::Callable(function) => match Rc::borrow(function) {
+                Function::WeakBound(stmt_function, closure, is_initializer, _) => {
+                    Value::Callable(Rc::new(Function::WeakBound(
+                        Rc::clone(stmt_function),
+                        Rc::clone(closure),
+                        *is_initializer,
+                        Rc::downgrade(&copy),
+                    )))
+                }
+                _ => field.clone(),
+            },
+            _ => deep_clone(paren, field, path)?,
+        };
+        copy.fields.borrow_mut().insert(*name, field);
+    }
+
+    Ok(Value::Instance(copy))
+}
+
+// Returns up to `count` characters of a string, starting at the character `start`. Ranges
+// reaching past the end of the string are cut short.
+fn native_substr(
+    _: &mut Interpreter,
+    interner: &Interner,
+    paren: &Token,
+    arguments: &[Value],
+) -> Result<Value, ErrCause> {
+    let (start, count) = match arguments[1..] {
+        [Value::Number(start), Value::Number(count)]
+            if start >= 0.0 && start.fract() == 0.0 && count >= 0.0 && count.fract() == 0.0 =>
+        {
+            (start as usize, count as usize)
+        }
+        _ => {
+            return Err(ErrCause::Error(
+                paren.clone(),
+                String::from("Start and count must be non-negative integers."),
+            ))
+        }
+    };
+
+    match arguments[0].with_str(interner, |string| {
+        string.chars().skip(start).take(count).collect::<String>()
+    }) {
+        Some(substring) => Ok(Value::String(Rc::new(substring))),
+        None => Err(ErrCause::Error(
+            paren.clone(),
+            String::from("Argument to substr() must be a string."),
+        )),
+    }
+}
+
+fn native_delete_field(
+    _: &mut Interpreter,
+    interner: &Interner,
+    paren: &Token,
+    arguments: &[Value],
+) -> Result<Value, ErrCause> {
+    let instance = match &arguments[0] {
+        Value::Instance(instance) => instance,
+        _ => {
+            return Err(ErrCause::Error(
+                paren.clone(),
+                String::from("Only instances have fields."),
+            ))
+        }
+    };
+
+    let name = match &arguments[1] {
+        Value::String(_) | Value::InternedString(_) => {
+            interner.get_or_intern(stringify(interner, &arguments[1], None))
+        }
+        _ => {
+            return Err(ErrCause::Error(
+                paren.clone(),
+                String::from("Field name must be a string."),
+            ))
+        }
+    };
+
+    instance.check_not_frozen(paren)?;
+    let removed = instance.fields.borrow_mut().remove(&name);
+    Ok(Value::Bool(removed.is_some()))
+}
+
+const MAX_FORMAT_DIGITS: f64 = 100.0;
+
+fn number_and_digits(paren: &Token, arguments: &[Value]) -> Result<(f64, usize), ErrCause> {
+    let num = match arguments[0] {
+        Value::Number(num) => num,
+        _ => {
+            return Err(ErrCause::Error(
+                paren.clone(),
+                String::from("Argument must be a number."),
+            ))
+        }
+    };
+
+    match arguments[1] {
+        Value::Number(digits)
+            if (0.0..=MAX_FORMAT_DIGITS).contains(&digits) && digits.fract() == 0.0 =>
+        {
+            Ok((num, digits as usize))
+        }
+        _ => Err(ErrCause::Error(
+            paren.clone(),
+            String::from("Digits must be an integer between 0 and 100."),
+        )),
+    }
+}
+
+// Renders instances with one field per line, lists with one element per line and maps with
+// one entry per line, indented by two spaces per level. Fields are sorted by name and map
+// entries by key. Other values print as usual, except that strings inside instances, lists
+// and maps are quoted.
+fn pretty_print(
+    interner: &Interner,
+    value: &Value,
+    precision: Option<usize>,
+    indent: usize,
+    path: &mut Vec<*const ()>,
+) -> String {
+    let (ptr, open, close, entries) = match value {
+        Value::Instance(instance) if !instance.class.is_enum => {
+            let mut fields: Vec<(Option<String>, Value)> = instance
+                .fields
+                .borrow()
+                .iter()
+                .map(|(name, field)| (Some(interner.resolve(*name)), field.clone()))
+                .collect();
+            fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+            let open = format!("{} instance {{", interner.resolve(instance.class.name));
+            (Rc::as_ptr(instance) as *const (), open, "}", fields)
+        }
+        Value::List(list) => {
+            let elements = RefCell::borrow(list)
+                .iter()
+                .map(|element| (None, element.clone()))
+                .collect();
+            (
+                Rc::as_ptr(list) as *const (),
+                String::from("["),
+                "]",
+                elements,
+            )
+        }
+        Value::Map(map) => {
+            let entries = RefCell::borrow(map)
+                .iter()
+                .map(|(key, value)| {
+                    let key = stringify_element(interner, &key.to_value(), precision, path);
+                    (Some(key), value.clone())
+                })
+                .collect();
+            (
+                Rc::as_ptr(map) as *const (),
+                String::from("{"),
+                "}",
+                entries,
+            )
+        }
+        _ => return stringify(interner, value, precision),
+    };
+
+    if path.contains(&ptr) {
+        return String::from("<cycle>");
+    }
+    if entries.is_empty() {
+        return format!("{}{}", open, close);
+    }
+
+    path.push(ptr);
+    let lines: Vec<String> = entries
+        .iter()
+        .map(|(name, entry)| {
+            let entry = match entry {
+                Value::String(_) | Value::InternedString(_) => {
+                    format!("\"{}\"", stringify(interner, entry, precision))
+                }
+                _ => pretty_print(interner, entry, precision, indent + 2, path),
+            };
+            match name {
+                Some(name) => format!("{:indent$}{}: {}", "", name, entry, indent = indent + 2),
+                None => format!("{:indent$}{}", "", entry, indent = indent + 2),
+            }
+        })
+        .collect();
+    path.pop();
+
+    format!(
+        "{}\n{}\n{:indent$}{}",
+        open,
+        lines.join(",\n"),
+        "",
+        close,
+        indent = indent
+    )
+}
+
+fn stringify(interner: &Interner, value: &Value, precision: Option<usize>) -> String {
     match value {
         Value::String(str) => str.as_ref().clone(),
         Value::InternedString(sym) => interner.resolve(*sym),
-        Value::Number(num) => format!("{}", num),
+        Value::Number(num) => match precision {
+            Some(precision) if num.is_finite() && *num != 0.0 => {
+                format_significant(*num, precision)
+            }
+            _ => format_g(*num),
+        },
         Value::Bool(b) => {
             if *b {
                 String::from("true")
@@ -890,17 +3482,110 @@ fn stringify(interner: &Interner, value: &Value) -> String {
             }
         }
         Value::Nil => String::from("nil"),
-        Value::Callable(function) => match &*Rc::borrow(function) {
-            Function::Native(..) => String::from("<native fn>"),
-            Function::Declared(stmt_function, ..) => {
-                let StmtFunction { name, .. } = Rc::borrow(stmt_function);
-                format!("<fn {}>", interner.resolve(name.lexeme))
+        Value::Callable(function) => match Rc::borrow(function) {
+            Function::Native(_, arity, _) => format!("<native fn/{}>", arity),
+            Function::Declared(stmt_function, ..) | Function::WeakBound(stmt_function, ..) => {
+                let StmtFunction { name, params, .. } = Rc::borrow(stmt_function);
+                format!("<fn {}/{}>", interner.resolve(name.lexeme), params.len())
             }
-            Function::Class(_, class) => interner.resolve(class.name),
+            Function::Class(_, class) | Function::Enum(class, _) => interner.resolve(class.name),
+            Function::Unbound(class, method) => format!(
+                "<unbound fn {}.{}/{}>",
+                interner.resolve(class.name),
+                interner.resolve(method.profile_name(interner)),
+                method.arity() + 1
+            ),
         },
         Value::Instance(instance) => {
             format!("{} instance", interner.resolve(instance.class.name))
         }
+        Value::Generator(generator) => {
+            format!(
+                "<generator {}>",
+                interner.resolve(generator.function.name.lexeme)
+            )
+        }
+        Value::Module(module) => format!("<module {}>", interner.resolve(module.path)),
+        Value::List(_) | Value::Map(_) => {
+            stringify_element(interner, value, precision, &mut Vec::new())
+        }
+    }
+}
+
+// Prints a value inside a list or map. Strings are quoted, lists print as '[1, "two"]' and
+// maps as '{"a": 1, 2: nil}' with their keys sorted. A collection that contains itself
+// prints as '[...]' or '{...}' where it reappears. `path` holds the collections being
+// printed.
+fn stringify_element(
+    interner: &Interner,
+    value: &Value,
+    precision: Option<usize>,
+    path: &mut Vec<*const ()>,
+) -> String {
+    match value {
+        Value::String(_) | Value::InternedString(_) => {
+            format!("\"{}\"", stringify(interner, value, precision))
+        }
+        Value::List(list) => {
+            let ptr = Rc::as_ptr(list) as *const ();
+            if path.contains(&ptr) {
+                return String::from("[...]");
+            }
+
+            path.push(ptr);
+            let elements: Vec<String> = RefCell::borrow(list)
+                .iter()
+                .map(|element| stringify_element(interner, element, precision, path))
+                .collect();
+            path.pop();
+
+            format!("[{}]", elements.join(", "))
+        }
+        Value::Map(map) => {
+            let ptr = Rc::as_ptr(map) as *const ();
+            if path.contains(&ptr) {
+                return String::from("{...}");
+            }
+
+            path.push(ptr);
+            let map = RefCell::borrow(map);
+            let entries: Vec<String> = map
+                .iter()
+                .map(|(key, value)| {
+                    format!(
+                        "{}: {}",
+                        stringify_element(interner, &key.to_value(), precision, path),
+                        stringify_element(interner, value, precision, path)
+                    )
+                })
+                .collect();
+            path.pop();
+
+            format!("{{{}}}", entries.join(", "))
+        }
+        _ => stringify(interner, value, precision),
+    }
+}
+
+// Checks that a value indexes one of the `len` elements of a list.
+fn list_index(bracket: &Token, index: &Value, len: usize) -> Result<usize, ErrCause> {
+    match *index {
+        Value::Number(num) if num.fract() == 0.0 => {
+            // Negative indices count back from the end, so -1 is the last element.
+            let adjusted = if num < 0.0 { num + len as f64 } else { num };
+            if adjusted >= 0.0 && adjusted < len as f64 {
+                Ok(adjusted as usize)
+            } else {
+                Err(ErrCause::Error(
+                    bracket.clone(),
+                    format!("List index {} out of range for length {}.", num, len),
+                ))
+            }
+        }
+        _ => Err(ErrCause::Error(
+            bracket.clone(),
+            String::from("List index must be an integer."),
+        )),
     }
 }
 
@@ -922,6 +3607,8 @@ macro_rules! env_ancestor {
 
 impl Environment {
     fn new(enclosing: Option<Rc<Environment>>) -> Environment {
+        LIVE_ENVIRONMENTS.with(|count| count.set(count.get() + 1));
+
         Environment {
             values: RefCell::new(FxHashMap::default()),
             enclosing,
@@ -932,6 +3619,31 @@ impl Environment {
         self.values.borrow_mut().insert(name, value);
     }
 
+    fn define_native(
+        &self,
+        interner: &Interner,
+        name: &str,
+        arity: usize,
+        function: NativeFunction,
+    ) {
+        self.define_native_with_optional(interner, name, arity, arity, function);
+    }
+
+    // Defines a native whose arguments after the first `required` may be left out.
+    fn define_native_with_optional(
+        &self,
+        interner: &Interner,
+        name: &str,
+        required: usize,
+        arity: usize,
+        function: NativeFunction,
+    ) {
+        self.define(
+            interner.get_or_intern(name),
+            Value::Callable(Rc::new(Function::Native(required, arity, function))),
+        );
+    }
+
     fn assign(&self, interner: &Interner, name: &Token, value: Value) -> Result<(), ErrCause> {
         if self.values.borrow().get(&name.lexeme).is_some() {
             self.values.borrow_mut().insert(name.lexeme, value);
@@ -960,13 +3672,12 @@ impl Environment {
         }
     }
 
-    fn get_at(environment: &Rc<Environment>, distance: usize, name: Symbol) -> Value {
+    fn get_at(environment: &Rc<Environment>, distance: usize, name: Symbol) -> Option<Value> {
         env_ancestor!(environment, distance)
             .values
             .borrow()
             .get(&name)
-            .unwrap()
-            .clone()
+            .cloned()
     }
 
     fn assign_at(
@@ -984,6 +3695,7 @@ impl Environment {
 enum FunctionType {
     None,
     Function,
+    Generator,
     Initializer,
     Method,
 }
@@ -993,63 +3705,171 @@ enum ClassType {
     None,
     Class,
     Subclass,
+    FieldInitializer,
 }
 
 struct Class {
     name: Symbol,
     methods: FxHashMap<Symbol, Value>,
     superclass: Option<Rc<Class>>,
+    fields: Vec<(Symbol, Option<Expr>)>,
+    field_environment: Rc<Environment>,
+    // Methods found in superclasses so far. Classes don't change once declared, so entries
+    // never go stale.
+    inherited_methods: RefCell<FxHashMap<Symbol, Value>>,
+    is_enum: bool,
 }
 
 impl Class {
-    fn find_method(&self, name: Symbol) -> Option<Value> {
-        self.methods.get(&name).cloned().or_else(|| {
-            self.superclass
+    fn inherits_from(&self, class: &Class) -> bool {
+        std::ptr::eq(self, class)
+            || self
+                .superclass
                 .as_ref()
-                .and_then(|superclass| superclass.find_method(name))
-        })
+                .is_some_and(|superclass| superclass.inherits_from(class))
+    }
+
+    fn find_method(&self, name: Symbol) -> Option<Value> {
+        if let Some(method) = self.methods.get(&name) {
+            return Some(method.clone());
+        }
+        if let Some(method) = self.inherited_methods.borrow().get(&name) {
+            return Some(method.clone());
+        }
+
+        let method = self.superclass.as_ref()?.find_method(name)?;
+        self.inherited_methods
+            .borrow_mut()
+            .insert(name, method.clone());
+        Some(method)
+    }
+}
+
+// Assertions recorded for a test summary or report. Failures are kept for these to report,
+// instead of ending the run.
+#[derive(Default)]
+pub(crate) struct TestResults {
+    pub(crate) cases: Vec<TestCase>,
+}
+
+#[derive(Clone)]
+pub(crate) struct TestCase {
+    // The source of the asserted condition, or the message given to 'require'.
+    pub(crate) name: String,
+    pub(crate) token: Token,
+    // Why the assertion failed, if it did.
+    pub(crate) failure: Option<String>,
+}
+
+impl TestResults {
+    fn record(&mut self, token: &Token, name: String, failure: Option<String>) {
+        self.cases.push(TestCase {
+            name,
+            token: token.clone(),
+            failure,
+        });
+    }
+}
+
+// The top-level declarations of a script imported with 'var name = import "path";'.
+struct Module {
+    path: Symbol,
+    environment: Rc<Environment>,
+}
+
+struct Generator {
+    function: Rc<StmtFunction>,
+    state: RefCell<GeneratorState>,
+}
+
+enum GeneratorState {
+    // Where the body stopped, starting with the frame of the body's block.
+    Suspended(Vec<Frame>),
+    Running,
+    Finished,
+}
+
+impl Generator {
+    fn new(function: Rc<StmtFunction>, environment: Rc<Environment>) -> Generator {
+        Generator {
+            function,
+            state: RefCell::new(GeneratorState::Suspended(vec![Frame::new(0, environment)])),
+        }
+    }
+}
+
+// The state of a statement a generator stopped inside of, see Interpreter::resume.
+struct Frame {
+    // The statement of a block to run next, the branch an 'if' took, the iterations a
+    // 'repeat' has left, the next element of a 'foreach', or whether a 'try' is catching.
+    index: usize,
+    // The environment of a block, or the one the statement runs in.
+    environment: Rc<Environment>,
+    // What a 'foreach' iterates over.
+    iterable: Value,
+}
+
+impl Frame {
+    fn new(index: usize, environment: Rc<Environment>) -> Frame {
+        Frame {
+            index,
+            environment,
+            iterable: Value::Nil,
+        }
     }
 }
 
 struct Instance {
     class: Rc<Class>,
     fields: RefCell<FxHashMap<Symbol, Value>>,
+    // Set by the freeze native; frozen instances reject field writes and deletions.
+    frozen: Cell<bool>,
 }
 
 trait RcInstanceExt {
-    fn get(&self, interner: &Interner, name: &Token) -> Result<Value, ErrCause>;
+    fn get_field(&self, interner: &Interner, name: Symbol) -> Option<Value>;
+    fn bind_method(&self, interner: &Interner, method: &Value) -> Value;
 }
 
 impl RcInstanceExt for Rc<Instance> {
-    fn get(&self, interner: &Interner, name: &Token) -> Result<Value, ErrCause> {
-        if let Some(value) = self.fields.borrow().get(&name.lexeme) {
-            Ok(value.clone())
-        } else if let Some(method) = self.class.find_method(name.lexeme) {
-            if let Value::Callable(function) = method {
-                if let Function::Declared(..) = &*Rc::borrow(&function) {
-                    Ok(Value::Callable(Rc::new(
-                        function.bind(interner, Rc::clone(self)),
-                    )))
-                } else {
-                    unreachable!()
-                }
-            } else {
-                unreachable!()
+    fn get_field(&self, interner: &Interner, name: Symbol) -> Option<Value> {
+        let fields = self.fields.borrow();
+        let value = fields.get(&name)?;
+        if let Value::Callable(function) = value {
+            if let Function::WeakBound(stmt_function, closure, is_initializer, _) =
+                Rc::borrow(function)
+            {
+                let method = Function::Declared(
+                    Rc::clone(stmt_function),
+                    Rc::clone(closure),
+                    *is_initializer,
+                );
+                return Some(Value::Callable(Rc::new(
+                    method.bind(interner, Rc::clone(self)),
+                )));
+            }
+        }
+        Some(value.clone())
+    }
+
+    fn bind_method(&self, interner: &Interner, method: &Value) -> Value {
+        if let Value::Callable(function) = method {
+            if let Function::Declared(..) = Rc::borrow(function) {
+                return Value::Callable(Rc::new(function.bind(interner, Rc::clone(self))));
             }
-        } else {
-            Err(ErrCause::Error(
-                name.clone(),
-                format!("Undefined property '{}'.", interner.resolve(name.lexeme)),
-            ))
         }
+        unreachable!()
     }
 }
 
 impl Instance {
     fn new(class: Rc<Class>) -> Instance {
+        LIVE_INSTANCES.with(|count| count.set(count.get() + 1));
+
         Instance {
             class,
             fields: RefCell::new(FxHashMap::default()),
+            frozen: Cell::new(false),
         }
     }
 
@@ -1057,7 +3877,76 @@ impl Instance {
         self.class.find_method(name)
     }
 
-    fn set(&self, name: &Token, value: Value) {
+    fn set(&self, interner: &Interner, name: &Token, value: Value) -> Result<(), ErrCause> {
+        self.check_not_frozen(name)?;
+        let value = self.weaken_self_binding(interner, value);
         self.fields.borrow_mut().insert(name.lexeme, value);
+        Ok(())
+    }
+
+    fn check_not_frozen(&self, token: &Token) -> Result<(), ErrCause> {
+        if self.frozen.get() {
+            Err(ErrCause::Error(
+                token.clone(),
+                String::from("Cannot modify a frozen instance."),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Storing a method bound to this very instance would make the instance own itself.
+    fn weaken_self_binding(&self, interner: &Interner, value: Value) -> Value {
+        if let Value::Callable(function) = &value {
+            if let Function::Declared(stmt_function, environment, is_initializer) =
+                Rc::borrow(function)
+            {
+                let bound_instance = environment
+                    .values
+                    .borrow()
+                    .get(&interner.sym_this)
+                    .and_then(Value::to_instance);
+                if let (Some(bound_instance), Some(closure)) =
+                    (bound_instance, &environment.enclosing)
+                {
+                    if std::ptr::eq(Rc::as_ptr(&bound_instance), self) {
+                        return Value::Callable(Rc::new(Function::WeakBound(
+                            Rc::clone(stmt_function),
+                            Rc::clone(closure),
+                            *is_initializer,
+                            Rc::downgrade(&bound_instance),
+                        )));
+                    }
+                }
+            }
+        }
+
+        value
+    }
+}
+
+// Counts of the objects currently allocated, for spotting leaks such as reference cycles.
+thread_local! {
+    static LIVE_ENVIRONMENTS: Cell<usize> = const { Cell::new(0) };
+    static LIVE_INSTANCES: Cell<usize> = const { Cell::new(0) };
+}
+
+impl Drop for Environment {
+    fn drop(&mut self) {
+        LIVE_ENVIRONMENTS.with(|count| count.set(count.get() - 1));
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        LIVE_INSTANCES.with(|count| count.set(count.get() - 1));
     }
 }
+
+pub fn live_environments() -> usize {
+    LIVE_ENVIRONMENTS.with(|count| count.get())
+}
+
+pub fn live_instances() -> usize {
+    LIVE_INSTANCES.with(|count| count.get())
+}
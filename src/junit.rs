@@ -0,0 +1,45 @@
+use crate::interpreter::TestCase;
+
+// Writes the assertions as a JUnit-style XML test suite, one test case per assertion, which
+// CI servers can read.
+pub fn report(cases: &[TestCase]) -> String {
+    let failures = cases.iter().filter(|case| case.failure.is_some()).count();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuite name=\"rlox\" tests=\"{}\" failures=\"{}\">\n",
+        cases.len(),
+        failures
+    ));
+    for case in cases {
+        let attributes = format!(
+            "name=\"{}\" line=\"{}\"",
+            escape(&case.name),
+            case.token.line
+        );
+        match &case.failure {
+            Some(failure) => xml.push_str(&format!(
+                "  <testcase {}>\n    <failure message=\"{}\"/>\n  </testcase>\n",
+                attributes,
+                escape(failure)
+            )),
+            None => xml.push_str(&format!("  <testcase {}/>\n", attributes)),
+        }
+    }
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
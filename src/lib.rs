@@ -0,0 +1,1193 @@
+mod ast;
+mod interner;
+mod interpreter;
+mod junit;
+mod number_format;
+mod parser;
+mod profiler;
+mod scanner;
+
+pub use crate::ast::{Expr, ExprId, ExprVariable, Stmt, StmtFunction};
+pub use crate::interner::Symbol;
+pub use crate::interpreter::{Interpreter, LoxValue};
+pub use crate::scanner::{LineIndex, Token, TokenLiteral, TokenType};
+
+use crate::interner::Interner;
+use crate::interpreter::{Resolver, TestCase};
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use std::cell::{Cell, RefCell};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io, str, thread};
+
+// Deeply recursive Lox programs need more stack than the main thread provides.
+pub const INTERPRETER_STACK_SIZE: usize = 256 * 1024 * 1024;
+
+pub struct App {
+    had_error: Cell<bool>,
+    runtime_error_kind: Cell<Option<RuntimeErrorKind>>,
+    // Stage of `run` in progress, used to classify compile errors.
+    stage: Cell<LoxErrorKind>,
+    errors: RefCell<Vec<LoxError>>,
+    // Lines of the source being run, for the columns of its errors.
+    line_index: RefCell<Option<LineIndex>>,
+    interner: interner::Interner,
+    // Shared by every parse, so that ids stay unique across the runs of a session.
+    expr_id_count: Cell<usize>,
+    // Stop after resolving, so scripts are only checked for compile errors.
+    check_only: bool,
+    // Print a MemoryReport to stderr after running a file.
+    mem_report: bool,
+    // Let line breaks end statements, making most semicolons optional.
+    newline_terminated: bool,
+    // Accept a final expression without a semicolon and print its value, like a REPL would.
+    repl_semantics: bool,
+    // Print the scanned tokens instead of parsing and running the source.
+    dump_tokens: bool,
+    // File to write a profile of the calls to after running a script.
+    profile_path: Option<String>,
+    // Warn about valid code that is likely a mistake.
+    lint: bool,
+    // List the keys of maps in canonical order instead of the order they were added.
+    sorted_maps: bool,
+    // Warn about variables that are read before anything was assigned to them.
+    warn_uninit: bool,
+    // Suggest fixes for common mistakes in compile errors.
+    friendly_errors: bool,
+    // Color error messages with ANSI escapes, for terminals.
+    color: bool,
+    // Count assertions and print how many passed and failed after running a script.
+    test_summary: bool,
+    // File to write a JUnit-style XML report of the assertions to, and the assertions of
+    // the scripts run so far that it reports.
+    junit_path: Option<String>,
+    junit_cases: RefCell<Vec<TestCase>>,
+    // Directory of the script file being run, which imports are relative to.
+    script_dir: RefCell<Option<PathBuf>>,
+    // Imported scripts currently running, innermost last, as their path and the path the
+    // import statement gave, which errors are reported with.
+    imports: RefCell<Vec<(PathBuf, String)>>,
+}
+
+// Approximate memory usage, as counts of the objects that are still allocated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryReport {
+    pub environments: usize,
+    pub pooled_environments: usize,
+    pub instances: usize,
+    pub interned_strings: usize,
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Memory report:")?;
+        writeln!(
+            f,
+            "  live environments: {} ({} pooled)",
+            self.environments, self.pooled_environments
+        )?;
+        writeln!(f, "  live instances: {}", self.instances)?;
+        write!(f, "  interned strings: {}", self.interned_strings)
+    }
+}
+
+// Exit codes follow jlox: 64 for usage errors, 65 for compile errors, 66 for unreadable
+// files and 70 for runtime errors. Failed assertions exit with 71 so that test runners can
+// tell them apart from other runtime errors.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RuntimeErrorKind {
+    Error,
+    AssertionFailure,
+}
+
+impl RuntimeErrorKind {
+    fn exit_code(self) -> i32 {
+        match self {
+            RuntimeErrorKind::Error => 70,
+            RuntimeErrorKind::AssertionFailure => 71,
+        }
+    }
+}
+
+// Limits for running untrusted source with `App::eval_sandboxed`. Limits that are None are
+// left off, though calls still can't nest deeper than the interpreter always allows.
+// Exceeding a limit is a runtime error that `try` doesn't catch.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SandboxLimits {
+    // Loop iterations and calls that may run in total.
+    pub steps: Option<usize>,
+    // How deep calls may nest.
+    pub call_depth: Option<usize>,
+    // Longest string, in bytes, that concatenation may build.
+    pub max_string_len: Option<usize>,
+}
+
+// An error reported while running a script, for hosts that handle errors themselves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoxError {
+    pub kind: LoxErrorKind,
+    pub line: u64,
+    // Where the token the error is about starts, in bytes from 1. Scan errors have none.
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LoxErrorKind {
+    Scan,
+    Parse,
+    Resolve,
+    Runtime,
+}
+
+// ANSI color codes for colored error messages.
+const RED: &str = "31";
+const YELLOW: &str = "33";
+const MAGENTA: &str = "35";
+
+impl Default for App {
+    fn default() -> Self {
+        App::new()
+    }
+}
+
+impl App {
+    pub fn new() -> App {
+        App {
+            had_error: Cell::new(false),
+            runtime_error_kind: Cell::new(None),
+            stage: Cell::new(LoxErrorKind::Scan),
+            errors: RefCell::new(Vec::new()),
+            line_index: RefCell::new(None),
+            interner: Interner::new(),
+            expr_id_count: Cell::new(0),
+            check_only: false,
+            mem_report: false,
+            newline_terminated: false,
+            repl_semantics: false,
+            dump_tokens: false,
+            profile_path: None,
+            lint: false,
+            sorted_maps: false,
+            warn_uninit: false,
+            friendly_errors: false,
+            color: false,
+            test_summary: false,
+            junit_path: None,
+            junit_cases: RefCell::new(Vec::new()),
+            script_dir: RefCell::new(None),
+            imports: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn new_interpreter(&self) -> Interpreter {
+        let mut interpreter = Interpreter::new(&self.interner);
+        if self.profile_path.is_some() {
+            interpreter.enable_profiler();
+        }
+        if self.test_summary || self.junit_path.is_some() {
+            interpreter.enable_test_results();
+        }
+        interpreter.set_sorted_maps(self.sorted_maps);
+        interpreter
+    }
+
+    // Allocates the id that variable, assignment, 'this' and 'super' expressions need, for
+    // building syntax trees without the parser.
+    pub fn next_expr_id(&self) -> ExprId {
+        let id = self.expr_id_count.get();
+        self.expr_id_count.set(id + 1);
+        ExprId(id)
+    }
+
+    pub fn intern(&self, string: &str) -> Symbol {
+        self.interner.get_or_intern(string)
+    }
+
+    pub fn set_check_only(&mut self, check_only: bool) {
+        self.check_only = check_only;
+    }
+
+    pub fn set_newline_terminated(&mut self, newline_terminated: bool) {
+        self.newline_terminated = newline_terminated;
+    }
+
+    pub fn set_repl_semantics(&mut self, repl_semantics: bool) {
+        self.repl_semantics = repl_semantics;
+    }
+
+    pub fn set_dump_tokens(&mut self, dump_tokens: bool) {
+        self.dump_tokens = dump_tokens;
+    }
+
+    pub fn set_mem_report(&mut self, mem_report: bool) {
+        self.mem_report = mem_report;
+    }
+
+    pub fn set_lint(&mut self, lint: bool) {
+        self.lint = lint;
+    }
+
+    pub fn set_sorted_maps(&mut self, sorted_maps: bool) {
+        self.sorted_maps = sorted_maps;
+    }
+
+    pub fn set_warn_uninit(&mut self, warn_uninit: bool) {
+        self.warn_uninit = warn_uninit;
+    }
+
+    pub fn set_friendly_errors(&mut self, friendly_errors: bool) {
+        self.friendly_errors = friendly_errors;
+    }
+
+    pub fn set_color(&mut self, color: bool) {
+        self.color = color;
+    }
+
+    pub fn set_test_summary(&mut self, test_summary: bool) {
+        self.test_summary = test_summary;
+    }
+
+    pub fn set_junit_path(&mut self, path: &str) {
+        self.junit_path = Some(String::from(path));
+    }
+
+    pub fn set_profile_path(&mut self, path: &str) {
+        self.profile_path = Some(String::from(path));
+    }
+
+    pub fn memory_report(&self, interpreter: &Interpreter) -> MemoryReport {
+        MemoryReport {
+            environments: interpreter::live_environments(),
+            pooled_environments: interpreter.pooled_environments(),
+            instances: interpreter::live_instances(),
+            interned_strings: self.interner.len(),
+        }
+    }
+
+    fn error(&self, line: u64, message: &str) {
+        self.report(line, None, "", message);
+    }
+
+    fn error_token(&self, token: &Token, message: &str) {
+        let column = self.column(token);
+        if token.token_type == TokenType::Eof {
+            self.report(token.line, column, " at end", message);
+        } else {
+            self.report(
+                token.line,
+                column,
+                &format!(" at '{}'", self.interner.resolve(token.lexeme)),
+                message,
+            );
+        }
+    }
+
+    fn column(&self, token: &Token) -> Option<usize> {
+        self.line_index.borrow().as_ref()?.column(token)
+    }
+
+    // Warnings are only printed; they don't make the script fail.
+    fn warning_token(&self, token: &Token, message: &str) {
+        eprintln!(
+            "{} {} at '{}': {}",
+            self.paint(&format!("[{}]", self.location(token.line)), YELLOW),
+            self.paint("Warning", MAGENTA),
+            self.interner.resolve(token.lexeme),
+            message
+        );
+    }
+
+    fn runtime_error(&self, kind: RuntimeErrorKind, token: &Token, message: &str) {
+        self.runtime_error_kind.set(Some(kind));
+        self.push_error(
+            LoxErrorKind::Runtime,
+            token.line,
+            self.column(token),
+            message,
+        );
+        eprintln!(
+            "{}\n{}",
+            self.paint(message, RED),
+            self.paint(&format!("[{}]", self.location(token.line)), YELLOW)
+        );
+    }
+
+    fn report(&self, line: u64, column: Option<usize>, origin: &str, message: &str) {
+        self.had_error.set(true);
+        self.push_error(self.stage.get(), line, column, message);
+        eprintln!(
+            "{} {}{}: {}",
+            self.paint(&format!("[{}]", self.location(line)), YELLOW),
+            self.paint("Error", RED),
+            origin,
+            message
+        );
+    }
+
+    fn paint(&self, text: &str, color: &str) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", color, text)
+        } else {
+            String::from(text)
+        }
+    }
+
+    // Errors in imported scripts also name the script.
+    fn location(&self, line: u64) -> String {
+        match self.imports.borrow().last() {
+            Some((_, name)) => format!("line {} in {}", line, name),
+            None => format!("line {}", line),
+        }
+    }
+
+    pub fn run_file(&self, interpreter: &mut Interpreter, path: &str) {
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                let path = Path::new(path);
+                if let Ok(canonical) = fs::canonicalize(path) {
+                    interpreter.mark_imported(canonical);
+                }
+                *self.script_dir.borrow_mut() = path.parent().map(Path::to_path_buf);
+                self.run_script(interpreter, &content)
+            }
+            _ => {
+                println!("Error: could not open file {}", path);
+                std::process::exit(66);
+            }
+        }
+    }
+
+    // Runs a whole script, such as a file's content, and exits on errors.
+    pub fn run_script(&self, interpreter: &mut Interpreter, source: &str) {
+        self.run(interpreter, source);
+        self.report_test_results(interpreter);
+        if self.mem_report {
+            eprintln!("{}", self.memory_report(interpreter));
+        }
+        if let (Some(path), Some(profile)) = (&self.profile_path, interpreter.folded_profile(self))
+        {
+            if let Err(error) = fs::write(path, profile) {
+                eprintln!("Error: could not write profile to {}: {}", path, error);
+            }
+        }
+        if self.had_error.get() {
+            std::process::exit(65);
+        }
+        if let Some(kind) = self.runtime_error_kind.get() {
+            std::process::exit(kind.exit_code());
+        }
+    }
+
+    // Reports the assertions recorded since the last call, if recording them is enabled:
+    // prints the failures and the summary, and writes the JUnit report with them added.
+    // Returns whether any of them failed.
+    fn report_test_results(&self, interpreter: &mut Interpreter) -> bool {
+        let Some(results) = interpreter.take_test_results() else {
+            return false;
+        };
+        let mut failed = 0;
+        for case in &results.cases {
+            if let Some(failure) = &case.failure {
+                self.runtime_error(RuntimeErrorKind::AssertionFailure, &case.token, failure);
+                failed += 1;
+            }
+        }
+        if self.test_summary {
+            println!("{} passed, {} failed", results.cases.len() - failed, failed);
+        }
+        if let Some(path) = &self.junit_path {
+            let mut cases = self.junit_cases.borrow_mut();
+            cases.extend(results.cases);
+            if let Err(error) = fs::write(path, junit::report(&cases)) {
+                eprintln!("Error: could not write JUnit report to {}: {}", path, error);
+            }
+        }
+        failed > 0
+    }
+
+    // Reads and runs lines until the end of the input. Assertions recorded for a test summary
+    // or JUnit report are reported after each line, and the exit code tells whether any of
+    // them failed.
+    pub fn run_prompt(&self, interpreter: &mut Interpreter) {
+        let mut line = String::with_capacity(1024);
+        let stdin = io::stdin();
+        let mut handle = stdin.lock();
+        let mut tests_failed = false;
+
+        loop {
+            print!("> ");
+            io::stdout().flush().expect("Could not flush stdout");
+
+            line.clear();
+            match handle.read_line(&mut line) {
+                Ok(bytes_read) => {
+                    if bytes_read == 0 {
+                        // we reached EOF (user probably pressed Ctrl+D)
+                        if tests_failed {
+                            std::process::exit(RuntimeErrorKind::AssertionFailure.exit_code());
+                        }
+                        std::process::exit(0);
+                    }
+                    if line.trim() == ":vars" {
+                        for (name, value) in interpreter.global_variables(self) {
+                            println!("{} = {}", name, value);
+                        }
+                        continue;
+                    }
+
+                    self.run(interpreter, &line);
+                    tests_failed |= self.report_test_results(interpreter);
+                    self.had_error.set(false);
+                }
+                Err(error) => {
+                    println!("Error: {}", error);
+                    std::process::exit(70);
+                }
+            }
+        }
+    }
+
+    // Runs the script at `path` in the interpreter's global scope, unless it already ran.
+    // Returns false if it could not be read or reported an error.
+    fn import(&self, interpreter: &mut Interpreter, keyword: &Token, path: &str) -> bool {
+        let Some((full_path, canonical, source)) = self.read_import(keyword, path) else {
+            return false;
+        };
+        if !interpreter.mark_imported(canonical) {
+            return true;
+        }
+
+        self.run_import(full_path, path, || self.run_stages(interpreter, &source))
+            .is_some()
+    }
+
+    // Reads the script an import names, relative to the script that imports it. Returns its
+    // path, canonical path and source.
+    fn read_import(&self, keyword: &Token, path: &str) -> Option<(PathBuf, PathBuf, String)> {
+        let importer_dir = match self.imports.borrow().last() {
+            Some((importer, _)) => importer.parent().map(Path::to_path_buf),
+            None => self.script_dir.borrow().clone(),
+        };
+        let full_path = importer_dir.unwrap_or_default().join(path);
+
+        match fs::canonicalize(&full_path).and_then(|canonical| {
+            let source = fs::read_to_string(&canonical)?;
+            Ok((canonical, source))
+        }) {
+            Ok((canonical, source)) => Some((full_path, canonical, source)),
+            Err(_) => {
+                self.runtime_error(
+                    RuntimeErrorKind::Error,
+                    keyword,
+                    &format!("Could not import '{}'.", path),
+                );
+                None
+            }
+        }
+    }
+
+    // Calls `run` with errors reported as coming from the imported script. Returns None if
+    // the script reported an error.
+    fn run_import<T>(&self, full_path: PathBuf, path: &str, run: impl FnOnce() -> T) -> Option<T> {
+        let previous_stage = self.stage.get();
+        let previous_runtime_error = self.runtime_error_kind.take();
+        let previous_line_index = self.line_index.take();
+        self.imports
+            .borrow_mut()
+            .push((full_path, String::from(path)));
+        let result = run();
+        self.imports.borrow_mut().pop();
+        self.stage.set(previous_stage);
+        *self.line_index.borrow_mut() = previous_line_index;
+
+        if self.had_error.get() || self.runtime_error_kind.get().is_some() {
+            return None;
+        }
+        self.runtime_error_kind.set(previous_runtime_error);
+        Some(result)
+    }
+
+    // Scans, parses and resolves a script imported as a module, whose top level is a scope
+    // of its own.
+    fn compile_module(&self, interpreter: &mut Interpreter, source: &str) -> Option<Vec<Stmt>> {
+        let statements = self.front_end(source)?;
+
+        self.stage.set(LoxErrorKind::Resolve);
+        Resolver::new(self, interpreter).resolve_module(&statements);
+
+        (!self.had_error.get()).then_some(statements)
+    }
+
+    fn push_error(&self, kind: LoxErrorKind, line: u64, column: Option<usize>, message: &str) {
+        self.errors.borrow_mut().push(LoxError {
+            kind,
+            line,
+            column,
+            message: String::from(message),
+        });
+    }
+
+    // Runs the source and returns the errors it reported, which are also printed to stderr.
+    pub fn run(&self, interpreter: &mut Interpreter, source: &str) -> Vec<LoxError> {
+        self.run_stages(interpreter, source);
+        self.errors.take()
+    }
+
+    // Scans without parsing or running, for testing the front end in isolation. The
+    // tokens refer to this app's interner, so pass them to its own `parse`. The line index
+    // maps the tokens' offsets to lines and columns.
+    pub fn scan(&self, source: &[u8]) -> (Vec<Token>, LineIndex, Vec<LoxError>) {
+        self.stage.set(LoxErrorKind::Scan);
+        let mut scanner = Scanner::new(self, source);
+        let tokens = scanner.scan_tokens();
+        self.had_error.set(false);
+        // Errors from parsing these tokens get columns too.
+        *self.line_index.borrow_mut() = Some(scanner.line_index());
+        (tokens, scanner.line_index(), self.errors.take())
+    }
+
+    pub fn parse(&self, tokens: Vec<Token>) -> (Vec<Stmt>, Vec<LoxError>) {
+        self.stage.set(LoxErrorKind::Parse);
+        let statements = Parser::new(self, tokens).parse();
+        self.had_error.set(false);
+        (statements, self.errors.take())
+    }
+
+    fn run_stages(&self, interpreter: &mut Interpreter, source: &str) {
+        if let Some(statements) = self.front_end(source) {
+            self.resolve_and_interpret(interpreter, &statements);
+        }
+    }
+
+    // Scans and parses the source. Returns None if that reported errors, or if the tokens
+    // were only dumped.
+    fn front_end(&self, source: &str) -> Option<Vec<Stmt>> {
+        self.stage.set(LoxErrorKind::Scan);
+        let mut scanner = Scanner::new(self, source.as_bytes());
+        let tokens = scanner.scan_tokens();
+        *self.line_index.borrow_mut() = Some(scanner.line_index());
+
+        if self.dump_tokens {
+            for token in &tokens {
+                println!(
+                    "{:4} {:?} '{}'",
+                    token.line,
+                    token.token_type,
+                    self.interner.resolve(token.lexeme)
+                );
+            }
+            return None;
+        }
+
+        self.stage.set(LoxErrorKind::Parse);
+        let mut parser = Parser::new(self, tokens);
+        let statements = parser.parse();
+
+        (!self.had_error.get()).then_some(statements)
+    }
+
+    // Runs the source with the limits applied and printing and importing disabled. Returns the
+    // value of the final expression statement, or nil if the source doesn't end with one, or
+    // the first error the source caused. Errors are also printed to stderr.
+    //
+    // The source runs in an app and interpreter of its own, on a thread with
+    // INTERPRETER_STACK_SIZE of stack, so that the call depth limits are hit before the stack
+    // overflows. Only this app's syntax and error display settings carry over, and its errors
+    // and interned strings are left untouched.
+    pub fn eval_sandboxed(
+        &self,
+        source: &str,
+        limits: &SandboxLimits,
+    ) -> Result<LoxValue, LoxError> {
+        let app = App {
+            newline_terminated: self.newline_terminated,
+            repl_semantics: self.repl_semantics,
+            dump_tokens: self.dump_tokens,
+            lint: self.lint,
+            sorted_maps: self.sorted_maps,
+            warn_uninit: self.warn_uninit,
+            friendly_errors: self.friendly_errors,
+            color: self.color,
+            ..App::new()
+        };
+
+        thread::scope(|scope| {
+            thread::Builder::new()
+                .stack_size(INTERPRETER_STACK_SIZE)
+                .spawn_scoped(scope, move || app.eval_limited(source, limits))
+                .expect("Could not spawn sandbox thread")
+                .join()
+                .expect("Sandbox thread panicked")
+        })
+    }
+
+    fn eval_limited(&self, source: &str, limits: &SandboxLimits) -> Result<LoxValue, LoxError> {
+        let mut interpreter = Interpreter::new(&self.interner);
+        interpreter.disable_io();
+        interpreter.set_sorted_maps(self.sorted_maps);
+        if let Some(steps) = limits.steps {
+            interpreter.set_step_budget(steps);
+        }
+        if let Some(depth) = limits.call_depth {
+            interpreter.set_function_depth_limit(depth);
+            interpreter.set_method_depth_limit(depth);
+        }
+        if let Some(limit) = limits.max_string_len {
+            interpreter.set_max_string_len(limit);
+        }
+
+        let mut value = None;
+        if let Some(statements) = self.front_end(source) {
+            self.stage.set(LoxErrorKind::Resolve);
+            Resolver::new(self, &mut interpreter).resolve(&statements);
+            if !self.had_error.get() {
+                value = interpreter.eval(self, &statements);
+            }
+        }
+
+        match (value, self.errors.take().into_iter().next()) {
+            (_, Some(error)) => Err(error),
+            // Nothing ran if the tokens were only dumped.
+            (value, None) => Ok(value.unwrap_or(LoxValue::Nil)),
+        }
+    }
+
+    // Runs statements built without the parser. Their tokens and ids must come from this app.
+    pub fn run_statements(
+        &self,
+        interpreter: &mut Interpreter,
+        statements: &[Stmt],
+    ) -> Vec<LoxError> {
+        // The tokens have no source to take columns from.
+        self.line_index.take();
+        self.resolve_and_interpret(interpreter, statements);
+        self.had_error.set(false);
+        self.errors.take()
+    }
+
+    fn resolve_and_interpret(&self, interpreter: &mut Interpreter, statements: &[Stmt]) {
+        self.stage.set(LoxErrorKind::Resolve);
+        let mut resolver = Resolver::new(self, interpreter);
+        resolver.resolve(statements);
+
+        if self.had_error.get() || self.check_only {
+            return;
+        }
+
+        interpreter.interpret(self, statements);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instance_storing_own_method_is_freed() {
+        let app = App::new();
+        let mut interpreter = Interpreter::new(&app.interner);
+
+        app.run(
+            &mut interpreter,
+            "class Node {
+              init() { this.self = this.method; }
+              method() { return this; }
+            }
+            var kept = Node();
+            fun make() { var node = Node(); node.self(); }
+            make();",
+        );
+
+        assert!(!app.had_error.get());
+        assert!(app.runtime_error_kind.get().is_none());
+        assert_eq!(interpreter::live_instances(), 1);
+    }
+
+    #[test]
+    fn test_local_enum_is_freed() {
+        let app = App::new();
+        let mut interpreter = app.new_interpreter();
+
+        app.run(
+            &mut interpreter,
+            "fun pick() {
+              enum Direction { UP, DOWN }
+              return Direction.DOWN.ordinal;
+            }
+            var kept;
+            {
+              enum Color { RED, GREEN }
+              kept = Color.GREEN;
+            }
+            assert pick() == 1;",
+        );
+
+        assert!(!app.had_error.get());
+        assert!(app.runtime_error_kind.get().is_none());
+        // Only the member that is still referenced survives its enum.
+        assert_eq!(interpreter::live_instances(), 1);
+    }
+
+    #[test]
+    fn test_define_global() {
+        let app = App::new();
+        let mut interpreter = app.new_interpreter();
+        interpreter.define_global(&app, "answer", LoxValue::from(42.0));
+        interpreter.define_global(&app, "greeting", LoxValue::from("hello"));
+        interpreter.define_global(&app, "enabled", LoxValue::from(true));
+
+        app.run(
+            &mut interpreter,
+            "assert answer == 42;
+            assert greeting + \" world\" == \"hello world\";
+            assert enabled;
+            // Injected globals behave like any other global.
+            answer = answer + 1;
+            assert answer == 43;",
+        );
+
+        assert!(!app.had_error.get());
+        assert!(app.runtime_error_kind.get().is_none());
+    }
+
+    #[test]
+    fn test_eval_sandboxed() {
+        let app = App::new();
+        let limits = SandboxLimits {
+            steps: Some(1000),
+            call_depth: Some(16),
+            max_string_len: Some(64),
+        };
+
+        let value = app.eval_sandboxed(
+            "fun square(n) { return n * n; }
+            var sum = 0;
+            for (var i = 1; i <= 3; i = i + 1) sum = sum + square(i);
+            sum;",
+            &limits,
+        );
+        assert_eq!(value, Ok(LoxValue::Number(14.0)));
+        assert_eq!(
+            app.eval_sandboxed("\"a\" + \"b\";", &limits),
+            Ok(LoxValue::from("ab"))
+        );
+        assert_eq!(app.eval_sandboxed("var a = 1;", &limits), Ok(LoxValue::Nil));
+
+        let runtime_error = |line, column, message: &str| {
+            Err(LoxError {
+                kind: LoxErrorKind::Runtime,
+                line,
+                column: Some(column),
+                message: String::from(message),
+            })
+        };
+        assert_eq!(
+            app.eval_sandboxed("var i = 0;\nwhile (true) i = i + 1;", &limits),
+            runtime_error(2, 1, "Step budget exceeded.")
+        );
+        assert_eq!(
+            app.eval_sandboxed("fun f() { f(); }\nf();", &limits),
+            runtime_error(1, 13, "Function call depth limit exceeded.")
+        );
+        assert_eq!(
+            app.eval_sandboxed("var s = \"ab\";\nwhile (true) s = s + s;", &limits),
+            runtime_error(2, 20, "String length limit exceeded.")
+        );
+
+        // Exceeding a limit can't be caught, not even in a generator.
+        assert_eq!(
+            app.eval_sandboxed("try {\n  while (true) {}\n} catch (e) {}\n42;", &limits),
+            runtime_error(2, 3, "Step budget exceeded.")
+        );
+        assert_eq!(
+            app.eval_sandboxed("fun f() { try { f(); } catch (e) {} }\nf();", &limits),
+            runtime_error(1, 19, "Function call depth limit exceeded.")
+        );
+        assert_eq!(
+            app.eval_sandboxed(
+                "var s = \"ab\";\ntry { while (true) s = s + s; } catch (e) {}",
+                &limits
+            ),
+            runtime_error(2, 26, "String length limit exceeded.")
+        );
+        assert_eq!(
+            app.eval_sandboxed(
+                "fun* g() { try { while (true) {} } catch (e) { yield e; } }\n\
+                 foreach (x in g()) print x;",
+                &limits
+            ),
+            runtime_error(1, 18, "Step budget exceeded.")
+        );
+        assert_eq!(
+            app.eval_sandboxed("print 1;", &limits),
+            runtime_error(1, 1, "IO is disabled.")
+        );
+        assert_eq!(
+            app.eval_sandboxed("1;\nwrite(\"x\");", &limits),
+            runtime_error(2, 10, "IO is disabled.")
+        );
+        assert_eq!(
+            app.eval_sandboxed("import \"lib.lox\";", &limits),
+            runtime_error(1, 1, "IO is disabled.")
+        );
+        assert_eq!(
+            app.eval_sandboxed("benchmark(\"f\", 1, clock);", &limits),
+            runtime_error(1, 24, "IO is disabled.")
+        );
+        assert!(matches!(
+            app.eval_sandboxed("1 +;", &limits),
+            Err(LoxError {
+                kind: LoxErrorKind::Parse,
+                ..
+            })
+        ));
+
+        // Without limits, recursion stops at the interpreter's own depth limit rather than
+        // overflowing the stack, and so does nesting in the parser.
+        let defaults = SandboxLimits::default();
+        assert_eq!(
+            app.eval_sandboxed("fun f() { f(); }\nf();", &defaults),
+            runtime_error(1, 13, "Stack overflow.")
+        );
+        let parens = format!("{}1{};", "(".repeat(20000), ")".repeat(20000));
+        assert_eq!(
+            app.eval_sandboxed(&parens, &defaults),
+            Err(LoxError {
+                kind: LoxErrorKind::Parse,
+                line: 1,
+                column: Some(256),
+                message: String::from("Too much nesting."),
+            })
+        );
+        let blocks = format!("{}1;{}", "{".repeat(20000), "}".repeat(20000));
+        assert!(matches!(
+            app.eval_sandboxed(&blocks, &defaults),
+            Err(LoxError {
+                kind: LoxErrorKind::Parse,
+                ..
+            })
+        ));
+
+        // Sandboxed runs leave the app's own error state alone.
+        assert!(!app.had_error.get());
+        assert!(app.errors.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_call_depth_limits() {
+        let recursion = "fun recurse(n) { if (n > 0) recurse(n - 1); }
+            class Node { recurse(n) { if (n > 0) this.recurse(n - 1); } }";
+
+        let run = |source: &str, function_limit, method_limit| {
+            let app = App::new();
+            let mut interpreter = app.new_interpreter();
+            interpreter.set_function_depth_limit(function_limit);
+            interpreter.set_method_depth_limit(method_limit);
+            app.run(&mut interpreter, &format!("{} {}", recursion, source));
+            app.runtime_error_kind.get()
+        };
+
+        assert_eq!(run("recurse(5);", 10, 10), None);
+        assert_eq!(run("Node().recurse(5);", 10, 10), None);
+
+        // Each limit only applies to its own kind of call.
+        assert_eq!(run("recurse(20);", 10, 100), Some(RuntimeErrorKind::Error));
+        assert_eq!(run("Node().recurse(20);", 10, 100), None);
+        assert_eq!(
+            run("Node().recurse(20);", 100, 10),
+            Some(RuntimeErrorKind::Error)
+        );
+        assert_eq!(run("recurse(20);", 100, 10), None);
+    }
+
+    #[test]
+    fn test_structured_errors() {
+        let errors_for = |source: &str| {
+            let app = App::new();
+            let mut interpreter = app.new_interpreter();
+            app.run(&mut interpreter, source)
+        };
+
+        let error = |kind, line, column, message: &str| LoxError {
+            kind,
+            line,
+            column,
+            message: String::from(message),
+        };
+
+        assert_eq!(errors_for("print 1;"), vec![]);
+        assert_eq!(
+            errors_for("print 1;\n@"),
+            vec![error(LoxErrorKind::Scan, 2, None, "Unexpected character.")]
+        );
+        assert_eq!(
+            errors_for("print;"),
+            vec![error(LoxErrorKind::Parse, 1, Some(6), "Expect expression.")]
+        );
+        assert_eq!(
+            errors_for("\nreturn 1;"),
+            vec![error(
+                LoxErrorKind::Resolve,
+                2,
+                Some(1),
+                "Can't return from top-level code."
+            )]
+        );
+        assert_eq!(
+            errors_for("var a;\n\na + 1;"),
+            vec![error(
+                LoxErrorKind::Runtime,
+                3,
+                Some(3),
+                "Operands must be two numbers or two strings."
+            )]
+        );
+    }
+
+    #[test]
+    fn test_scan_and_parse() {
+        let app = App::new();
+        let (tokens, _, errors) = app.scan(b"print 1 +;");
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(errors, vec![]);
+
+        let (statements, errors) = app.parse(tokens);
+        assert!(statements.is_empty());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, LoxErrorKind::Parse);
+
+        let (_, _, errors) = app.scan(b"\"\xff\"");
+        assert_eq!(errors[0].message, "Invalid UTF-8 in string.");
+    }
+
+    #[test]
+    fn test_token_offsets() {
+        let app = App::new();
+        let source = "var name = \"é\";\n  print name >= 1.5; // done";
+        let (tokens, _, _) = app.scan(source.as_bytes());
+
+        let spans: Vec<(&str, u64)> = tokens
+            .iter()
+            .map(|token| (&source[token.start..token.start + token.len], token.line))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("var", 1),
+                ("name", 1),
+                ("=", 1),
+                ("\"é\"", 1),
+                (";", 1),
+                ("print", 2),
+                ("name", 2),
+                (">=", 2),
+                ("1.5", 2),
+                (";", 2),
+                ("", 2),
+            ]
+        );
+        assert_eq!(tokens.last().unwrap().start, source.len());
+    }
+
+    #[test]
+    fn test_line_index() {
+        let app = App::new();
+        let source = "var a = 1;\r\nprint \"two\nlines\";\rprint a;\n\n";
+        let (tokens, line_index, _) = app.scan(source.as_bytes());
+
+        assert_eq!(line_index.position(0), (1, 1));
+        assert_eq!(line_index.position(8), (1, 9));
+        // The "\r\n" belongs to the line it ends.
+        assert_eq!(line_index.position(11), (1, 12));
+        assert_eq!(line_index.position(12), (2, 1));
+        // Line breaks inside strings count too.
+        assert_eq!(line_index.position(23), (3, 1));
+        assert_eq!(line_index.position(24), (3, 2));
+        assert_eq!(line_index.position(31), (4, 1));
+        assert_eq!(line_index.position(37), (4, 7));
+        assert_eq!(line_index.position(source.len()), (6, 1));
+
+        // Tokens report the line they end on.
+        for token in &tokens {
+            let end = token.start + token.len.saturating_sub(1);
+            assert_eq!(line_index.position(end).0, token.line);
+        }
+
+        let columns: Vec<Option<usize>> = tokens
+            .iter()
+            .map(|token| line_index.column(token))
+            .collect();
+        assert_eq!(
+            columns,
+            vec![
+                Some(1),
+                Some(5),
+                Some(7),
+                Some(9),
+                Some(10),
+                Some(1),
+                // The string starts on line 2 but reports line 3.
+                None,
+                Some(7),
+                Some(1),
+                Some(7),
+                Some(8),
+                Some(1),
+            ]
+        );
+
+        // Tokens from a longer source have no column here.
+        let (other_tokens, _, _) = app.scan(format!("{}var b;", source).as_bytes());
+        assert_eq!(
+            line_index.column(&other_tokens[other_tokens.len() - 2]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_front_end_never_panics() {
+        // Inputs mix Lox fragments with arbitrary bytes, so that they get past the scanner
+        // often enough to exercise the parser too.
+        const FRAGMENTS: [&[u8]; 16] = [
+            b"(", b")", b"{", b"}", b";", b",", b".", b"=", b"+", b"\"", b"1.5", b"x", b" fun ",
+            b" class ", b" for ", b"\xc3",
+        ];
+
+        let app = App::new();
+        let mut state: u64 = 0x2545f4914f6cdd1d;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..2000 {
+            let mut source = Vec::new();
+            for _ in 0..next() % 40 {
+                let choice = next();
+                if choice % 4 == 0 {
+                    source.push((choice >> 8) as u8);
+                } else {
+                    source.extend_from_slice(FRAGMENTS[(choice >> 8) as usize % FRAGMENTS.len()]);
+                }
+            }
+
+            let (tokens, _, _) = app.scan(&source);
+            app.parse(tokens);
+        }
+    }
+
+    #[test]
+    fn test_run_statements() {
+        let app = App::new();
+        let mut interpreter = app.new_interpreter();
+
+        let token = |token_type, lexeme| Token {
+            token_type,
+            lexeme: app.intern(lexeme),
+            literal: TokenLiteral::Nil,
+            line: 1,
+            start: 0,
+            len: 0,
+        };
+        let number = |value| Expr::Literal {
+            value: TokenLiteral::Number(value),
+        };
+        let variable = |name| Expr::Variable(app.next_expr_id(), ExprVariable { name });
+
+        // { var x = 1 + 2; assert x == <expected>; }
+        let program = |expected| {
+            vec![Stmt::Block {
+                statements: vec![
+                    Stmt::Var {
+                        name: token(TokenType::Identifier, "x"),
+                        initializer: Some(Expr::Binary {
+                            left: Box::new(number(1.0)),
+                            operator: token(TokenType::Plus, "+"),
+                            right: Box::new(number(2.0)),
+                        }),
+                    },
+                    Stmt::Assert {
+                        keyword: token(TokenType::Assert, "assert"),
+                        condition: Expr::Binary {
+                            left: Box::new(variable(token(TokenType::Identifier, "x"))),
+                            operator: token(TokenType::EqualEqual, "=="),
+                            right: Box::new(number(expected)),
+                        },
+                        source: format!("x == {}", expected),
+                    },
+                ],
+            }]
+        };
+
+        assert_eq!(app.run_statements(&mut interpreter, &program(3.0)), vec![]);
+
+        let errors = app.run_statements(&mut interpreter, &program(4.0));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, LoxErrorKind::Runtime);
+        assert_eq!(errors[0].message, "Assertion failed: x == 4");
+    }
+
+    #[test]
+    fn test_memory_report() {
+        let app = App::new();
+        let mut interpreter = app.new_interpreter();
+        let leaked = |app: &App, interpreter: &Interpreter| {
+            let report = app.memory_report(interpreter);
+            (
+                report.environments - report.pooled_environments,
+                report.instances,
+            )
+        };
+
+        app.run(
+            &mut interpreter,
+            "class Point { init(x) { this.x = x; } }
+            fun work(n) {
+                var point = Point(n);
+                { var copy = point; }
+                return point.x;
+            }",
+        );
+        let baseline = leaked(&app, &interpreter);
+
+        app.run(
+            &mut interpreter,
+            "for (var i = 0; i < 10; i = i + 1) work(i);",
+        );
+        assert_eq!(leaked(&app, &interpreter), baseline);
+
+        app.run(&mut interpreter, "var kept = Point(1);");
+        assert_eq!(leaked(&app, &interpreter), (baseline.0, baseline.1 + 1));
+    }
+
+    #[test]
+    fn test_map_keys_are_not_interned() {
+        let app = App::new();
+        let mut interpreter = app.new_interpreter();
+
+        app.run(
+            &mut interpreter,
+            "var map = {};
+            var count = 10;
+            fun fill(n) {
+                for (var i = 0; i < n; i = i + 1) {
+                    var key = \"key\" + toFixed(i, 0);
+                    map[key] = i;
+                    assert map[key] == i;
+                    assert has(map, key);
+                }
+            }",
+        );
+        let interned = app.memory_report(&interpreter).interned_strings;
+
+        app.run(&mut interpreter, "fill(count);");
+
+        assert!(!app.had_error.get());
+        assert!(app.runtime_error_kind.get().is_none());
+        assert_eq!(app.memory_report(&interpreter).interned_strings, interned);
+    }
+}
@@ -1,148 +1,99 @@
-mod ast;
-mod interner;
-mod interpreter;
-mod parser;
-mod scanner;
-
-use crate::interner::{Interner, Symbol};
-use crate::interpreter::{Interpreter, Resolver};
-use crate::parser::Parser;
-use crate::scanner::{Scanner, Token, TokenType};
-use std::cell::Cell;
-use std::io::{BufRead, Write};
-use std::{env, fs, io, str};
+use rlox_interpreter::{App, INTERPRETER_STACK_SIZE};
+use std::io::IsTerminal;
+use std::{env, io, thread};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    let app = App::new();
-    let mut interpreter = Interpreter::new(&app.interner);
-
-    match &args[..] {
-        [_] => app.run_prompt(&mut interpreter),
-        [_, path] => app.run_file(&mut interpreter, path),
-        _ => {
-            println!("Usage: rlox-interpreter [script]");
-            std::process::exit(64);
-        }
-    }
+    thread::Builder::new()
+        .stack_size(INTERPRETER_STACK_SIZE)
+        .spawn(move || run_main(args))
+        .expect("Could not spawn interpreter thread")
+        .join()
+        .expect("Interpreter thread panicked");
 }
 
-pub struct App {
-    had_error: Cell<bool>,
-    had_runtime_error: Cell<bool>,
-    interner: interner::Interner,
-}
+fn run_main(args: Vec<String>) {
+    let mut app = App::new();
+    app.set_color(io::stderr().is_terminal());
+    let mut paths = Vec::new();
+    let mut source = None;
+    let mut strict_bool = false;
+    let mut max_string_len = None;
+    let mut args = args.iter().skip(1);
 
-impl App {
-    fn new() -> App {
-        App {
-            had_error: Cell::new(false),
-            had_runtime_error: Cell::new(false),
-            interner: Interner::new(),
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" => match args.next() {
+                Some(arg) if source.is_none() => source = Some(arg),
+                _ => usage(),
+            },
+            "--repl-semantics" => app.set_repl_semantics(true),
+            "--check" => app.set_check_only(true),
+            "--dump-tokens" => app.set_dump_tokens(true),
+            "--mem-report" => app.set_mem_report(true),
+            "--newline-terminated" => app.set_newline_terminated(true),
+            "--strict-bool" => strict_bool = true,
+            "--lint" => app.set_lint(true),
+            "--sorted-maps" => app.set_sorted_maps(true),
+            "--warn-uninit" => app.set_warn_uninit(true),
+            "--friendly-errors" => app.set_friendly_errors(true),
+            "--test-summary" => app.set_test_summary(true),
+            "--color=auto" => app.set_color(io::stderr().is_terminal()),
+            "--color=always" => app.set_color(true),
+            "--color=never" => app.set_color(false),
+            "--max-string-len" => match args.next().and_then(|arg| arg.parse().ok()) {
+                Some(limit) => max_string_len = Some(limit),
+                None => usage(),
+            },
+            "--junit" => match args.next() {
+                Some(path) => app.set_junit_path(path),
+                None => usage(),
+            },
+            "--profile" => match args.next() {
+                Some(path) => app.set_profile_path(path),
+                None => usage(),
+            },
+            _ if arg.starts_with("--") => usage(),
+            _ => paths.push(arg),
         }
     }
 
-    fn error(&self, line: u64, message: &str) {
-        self.report(line, "", message);
-    }
-
-    fn error_token(&self, token: &Token, message: &str) {
-        if token.token_type == TokenType::Eof {
-            self.report(token.line, " at end", message);
-        } else {
-            self.report(
-                token.line,
-                &format!(" at '{}'", self.interner.resolve(token.lexeme)),
-                message,
-            );
-        }
-    }
-
-    fn runtime_error(&self, token: &Token, message: &str) {
-        self.had_runtime_error.set(true);
-        eprintln!("{}\n[line {}]", message, token.line);
-    }
-
-    fn report(&self, line: u64, origin: &str, message: &str) {
-        self.had_error.set(true);
-        eprintln!("[line {}] Error{}: {}", line, origin, message);
-    }
-
-    fn run_file(&self, interpreter: &mut Interpreter, path: &str) {
-        match fs::read_to_string(path) {
-            Ok(content) => {
-                self.run(interpreter, &content);
-                if self.had_error.get() {
-                    std::process::exit(65);
-                }
-            }
-            _ => {
-                println!("Error: could not open file {}", path);
-                std::process::exit(66);
-            }
-        }
+    let mut interpreter = app.new_interpreter();
+    interpreter.set_strict_bool(strict_bool);
+    if let Some(limit) = max_string_len {
+        interpreter.set_max_string_len(limit);
     }
 
-    fn run_prompt(&self, interpreter: &mut Interpreter) {
-        let mut line = String::with_capacity(1024);
-        let stdin = io::stdin();
-        let mut handle = stdin.lock();
-
-        loop {
-            print!("> ");
-            io::stdout().flush().expect("Could not flush stdout");
-
-            line.clear();
-            match handle.read_line(&mut line) {
-                Ok(bytes_read) => {
-                    if bytes_read == 0 {
-                        // we reached EOF (user probably pressed Ctrl+D)
-                        std::process::exit(0);
-                    }
-                    self.run(interpreter, &line);
-                    self.had_error.set(false);
-                }
-                Err(error) => {
-                    println!("Error: {}", error);
-                    std::process::exit(70);
-                }
+    match (source, &paths[..]) {
+        (Some(source), []) => app.run_script(&mut interpreter, source),
+        (None, []) => app.run_prompt(&mut interpreter),
+        // Files share the interpreter, so later ones can use what earlier ones defined.
+        // run_file exits on the first error.
+        (None, paths) => {
+            for path in paths {
+                app.run_file(&mut interpreter, path);
             }
         }
+        _ => usage(),
     }
+}
 
-    fn run(&self, interpreter: &mut Interpreter, source: &str) {
-        let mut scanner = Scanner::new(self, source.as_bytes());
-        let tokens = scanner.scan_tokens();
-        let mut parser = Parser::new(self, tokens);
-        let statements = parser.parse();
-
-        if self.had_error.get() {
-            return;
-        }
-
-        let mut resolver = Resolver::new(self, interpreter);
-        resolver.resolve(&statements);
-
-        if self.had_error.get() {
-            return;
-        }
-
-        interpreter.interpret(self, &statements);
-    }
+fn usage() -> ! {
+    println!("Usage: rlox-interpreter [--check] [--color=auto|always|never] [--dump-tokens] [--friendly-errors] [--junit file] [--lint] [--max-string-len bytes] [--mem-report] [--newline-terminated] [--profile file] [--repl-semantics] [--sorted-maps] [--strict-bool] [--test-summary] [--warn-uninit] [-e source | script...]");
+    std::process::exit(64);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
+    use std::io::Write;
     use std::path::PathBuf;
     use walkdir::WalkDir;
 
-    #[test]
-    fn test_compliance() {
-        let root_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-
-        let mut exe_path = root_dir.clone();
+    fn exe_path() -> PathBuf {
+        let mut exe_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         exe_path.push(format!(
             "target/debug/rlox-interpreter{}",
             std::env::consts::EXE_SUFFIX
@@ -153,7 +104,30 @@ mod tests {
             "rlox-interpreter executable not found. Run cargo build first."
         );
 
-        let mut resources_dir = root_dir;
+        exe_path
+    }
+
+    fn run_source(name: &str, source: &str) -> std::process::Output {
+        run_source_with_args(name, source, &[])
+    }
+
+    fn run_source_with_args(name: &str, source: &str, args: &[&str]) -> std::process::Output {
+        let mut script_path = env::temp_dir();
+        script_path.push(format!("rlox-interpreter-{}.lox", name));
+        fs::write(&script_path, source).unwrap();
+
+        std::process::Command::new(exe_path())
+            .args(args)
+            .arg(&script_path)
+            .output()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_compliance() {
+        let exe_path = exe_path();
+
+        let mut resources_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         resources_dir.push("resources/compliance_tests");
 
         for lox_file in WalkDir::new(resources_dir)
@@ -189,6 +163,813 @@ mod tests {
                 "Unexpected stderr-output for {}.",
                 lox_file_path
             );
+
+            // The expected exit code is only checked for tests that provide one.
+            if let Ok(expected_exit) = fs::read_to_string(String::from(lox_file_path) + ".exit") {
+                assert_eq!(
+                    output.status.code(),
+                    Some(expected_exit.trim().parse().unwrap()),
+                    "Unexpected exit code for {}.",
+                    lox_file_path
+                );
+            }
+        }
+    }
+
+    // Compliance cases that are small enough to live in one table instead of three files:
+    // (name, source, expected stdout, expected stderr, expected exit code).
+    const COMPLIANCE_CASES: &[(&str, &str, &str, &str, i32)] = &[
+        ("empty", "", "", "", 0),
+        ("print", "print 1 + 2;", "3\n", "", 0),
+        (
+            "runtime_error",
+            "print 1;\nprint -nil;",
+            "1\n",
+            "Operand must be a number.\n[line 2]\n",
+            70,
+        ),
+        (
+            "compile_error",
+            "print 1;\nprint;",
+            "",
+            "[line 2] Error at ';': Expect expression.\n",
+            65,
+        ),
+        (
+            "assertion_failure",
+            "var a = 1;\nassert a == 2;",
+            "",
+            "Assertion failed: a == 2\n[line 2]\n",
+            71,
+        ),
+        (
+            "catch_thrown_instance",
+            "class E {} var e = E(); try { throw e; } catch (c) { print c == e; }",
+            "true\n",
+            "",
+            0,
+        ),
+        (
+            "uncaught_throw",
+            "throw \"bad\";",
+            "",
+            "bad\n[line 1]\n",
+            70,
+        ),
+    ];
+
+    #[test]
+    fn test_compliance_cases() {
+        for (name, source, expected_out, expected_err, expected_exit) in COMPLIANCE_CASES {
+            let output = run_source(&format!("case-{}", name), source);
+
+            assert_eq!(
+                String::from_utf8(output.stdout).unwrap(),
+                *expected_out,
+                "Unexpected stdout-output for case {}.",
+                name
+            );
+            assert_eq!(
+                String::from_utf8(output.stderr).unwrap(),
+                *expected_err,
+                "Unexpected stderr-output for case {}.",
+                name
+            );
+            assert_eq!(
+                output.status.code(),
+                Some(*expected_exit),
+                "Unexpected exit code for case {}.",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_benchmark_native() {
+        let output = run_source(
+            "benchmark",
+            "var calls = 0;
+            fun f() { calls = calls + 1; }
+            var average = benchmark(\"f\", 3, f);
+            print calls;
+            print average >= 0;",
+        );
+
+        assert_eq!(output.status.code(), Some(0));
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines.len(), 3, "Unexpected output: {}", stdout);
+        assert!(lines[0].starts_with("f: 3 iterations in "), "{}", lines[0]);
+        assert!(lines[0].ends_with(" s per iteration)"), "{}", lines[0]);
+        assert_eq!(lines[1], "3");
+        assert_eq!(lines[2], "true");
+    }
+
+    #[test]
+    fn test_check_flag() {
+        // Valid programs are not run, even if they would fail at runtime.
+        let output = run_source_with_args(
+            "check-valid",
+            "print \"not printed\"; nil + 1;",
+            &["--check"],
+        );
+        assert_eq!(output.status.code(), Some(0));
+        assert!(output.stdout.is_empty());
+        assert!(output.stderr.is_empty());
+
+        let output = run_source_with_args("check-parse-error", "print 1 +;", &["--check"]);
+        assert_eq!(output.status.code(), Some(65));
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "[line 1] Error at ';': Expect expression.\n"
+        );
+
+        let output = run_source_with_args("check-resolve-error", "return 1;", &["--check"]);
+        assert_eq!(output.status.code(), Some(65));
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "[line 1] Error at 'return': Can't return from top-level code.\n"
+        );
+
+        let output = run_source_with_args("check-unknown-flag", "", &["--chekc"]);
+        assert_eq!(output.status.code(), Some(64));
+    }
+
+    #[test]
+    fn test_mem_report_flag() {
+        let output = run_source_with_args(
+            "mem-report",
+            "class A {} var a = A(); print \"done\";",
+            &["--mem-report"],
+        );
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "done\n");
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.starts_with("Memory report:\n"), "{}", stderr);
+        assert!(stderr.contains("  live instances: 1\n"), "{}", stderr);
+    }
+
+    #[test]
+    fn test_newline_terminated_flag() {
+        let source = "var a = 1
+            var b = a +
+              2
+            print b
+            fun add(x, y) { return x + y }
+            print add(
+              a,
+              b
+            )
+            var c = a
+            -1
+            print c
+            print (a
+              - 5)
+            print \"x\"; print \"y\"";
+
+        let output = run_source_with_args("newline-terminated", source, &["--newline-terminated"]);
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "",
+            "Unexpected errors"
+        );
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "3\n4\n1\n-4\nx\ny\n"
+        );
+
+        // Two statements on one line still need a semicolon between them.
+        let output = run_source_with_args(
+            "newline-terminated-same-line",
+            "print 1 print 2",
+            &["--newline-terminated"],
+        );
+        assert_eq!(output.status.code(), Some(65));
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "[line 1] Error at 'print': Expect ';' after value.\n"
+        );
+
+        // The closing brace ends the last statement of a block on the same line.
+        let output = run_source_with_args(
+            "newline-terminated-block",
+            "fun f() { print 2 } { print 1 } { f() }",
+            &["--newline-terminated"],
+        );
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n2\n");
+
+        // A line starting with '[' begins a list literal rather than indexing the line before.
+        let output = run_source_with_args(
+            "newline-terminated-list",
+            "var a = [1,\n  2]\nprint a\n[3]\nprint a[1]",
+            &["--newline-terminated"],
+        );
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "[1, 2]\n2\n");
+
+        // Without the flag, semicolons stay required.
+        let output = run_source("semicolons-required", "print 1\nprint 2;");
+        assert_eq!(output.status.code(), Some(65));
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "[line 2] Error at 'print': Expect ';' after value.\n"
+        );
+
+        let output = run_source("semicolons-required-block", "{ print 1 }");
+        assert_eq!(output.status.code(), Some(65));
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "[line 1] Error at '}': Expect ';' after value.\n[line 1] Error at end: Expect '}' after block.\n"
+        );
+    }
+
+    fn run_args(args: &[&str]) -> std::process::Output {
+        std::process::Command::new(exe_path())
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_multiple_files() {
+        let mut library = env::temp_dir();
+        library.push("rlox-interpreter-multiple-files-library.lox");
+        fs::write(
+            &library,
+            "var greeting = \"Hello\";\nfun greet(name) { return greeting + \", \" + name; }\n",
+        )
+        .unwrap();
+        let mut main = env::temp_dir();
+        main.push("rlox-interpreter-multiple-files-main.lox");
+        fs::write(&main, "print greet(\"world\");\n").unwrap();
+        let mut failing = env::temp_dir();
+        failing.push("rlox-interpreter-multiple-files-failing.lox");
+        fs::write(&failing, "print greeting;\nprint -greeting;\n").unwrap();
+
+        let output = std::process::Command::new(exe_path())
+            .args([&library, &main])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "Hello, world\n");
+
+        // Files after the first one that fails are not run.
+        let output = std::process::Command::new(exe_path())
+            .args([&library, &failing, &main])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(70));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "Hello\n");
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "Operand must be a number.\n[line 2]\n"
+        );
+    }
+
+    #[test]
+    fn test_max_string_len_flag() {
+        let source = "var s = \"ab\";
+            while (true) {
+              s = s + s;
+              print s;
+            }";
+
+        let output = run_source_with_args("max-string-len", source, &["--max-string-len", "8"]);
+        assert_eq!(output.status.code(), Some(70));
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "abab\nabababab\n"
+        );
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "String length limit exceeded.\n[line 3]\n"
+        );
+
+        // A huge string trips a realistic limit long before memory runs out.
+        let output = run_source_with_args(
+            "max-string-len-huge",
+            "var s = \"x\"; while (true) s = s + s;",
+            &["--max-string-len", "1000000"],
+        );
+        assert_eq!(output.status.code(), Some(70));
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "String length limit exceeded.\n[line 1]\n"
+        );
+
+        let output = run_args(&["--max-string-len", "many", "-e", ""]);
+        assert_eq!(output.status.code(), Some(64));
+    }
+
+    #[test]
+    fn test_color_flag() {
+        let output = run_args(&["--color=always", "-e", "print;"]);
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "\x1b[33m[line 1]\x1b[0m \x1b[31mError\x1b[0m at ';': Expect expression.\n"
+        );
+
+        let output = run_args(&["--color=always", "-e", "print -nil;"]);
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "\x1b[31mOperand must be a number.\x1b[0m\n\x1b[33m[line 1]\x1b[0m\n"
+        );
+
+        // Output that isn't a terminal stays plain by default.
+        for args in [&["-e", "print;"][..], &["--color=auto", "-e", "print;"]] {
+            let output = run_args(args);
+            assert_eq!(
+                String::from_utf8(output.stderr).unwrap(),
+                "[line 1] Error at ';': Expect expression.\n"
+            );
+        }
+
+        let output = run_args(&["--color=sometimes", "-e", ""]);
+        assert_eq!(output.status.code(), Some(64));
+    }
+
+    #[test]
+    fn test_summary_flag() {
+        let source = "assert 1 < 2;
+            assert 1 > 2;
+            require(true, \"first\");
+            require(false, \"second\");
+            print \"done\";";
+
+        let output = run_source_with_args("test-summary", source, &["--test-summary"]);
+        assert_eq!(output.status.code(), Some(71));
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "done\n2 passed, 2 failed\n"
+        );
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "Assertion failed: 1 > 2\n[line 2]\nsecond\n[line 4]\n"
+        );
+
+        let output = run_source_with_args("test-summary-pass", "assert true;", &["--test-summary"]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "1 passed, 0 failed\n"
+        );
+    }
+
+    #[test]
+    fn test_junit_flag() {
+        let mut report_path = env::temp_dir();
+        report_path.push("rlox-interpreter-junit.xml");
+        let _ = fs::remove_file(&report_path);
+
+        let source = "var a = 1;
+            assert a == 1;
+            assert a < 0 and \"<x>\" != \"y\";
+            require(a == 1, \"a is one\");
+            require(false, \"this & that\");";
+        let output =
+            run_source_with_args("junit", source, &["--junit", report_path.to_str().unwrap()]);
+
+        // Failures are still reported, but no summary is printed without --test-summary.
+        assert_eq!(output.status.code(), Some(71));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "");
+        assert_eq!(
+            fs::read_to_string(&report_path).unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuite name="rlox" tests="4" failures="2">
+  <testcase name="a == 1" line="2"/>
+  <testcase name="a &lt; 0 and &quot;&lt;x&gt;&quot; != &quot;y&quot;" line="3">
+    <failure message="Assertion failed: a &lt; 0 and &quot;&lt;x&gt;&quot; != &quot;y&quot;"/>
+  </testcase>
+  <testcase name="a is one" line="4"/>
+  <testcase name="this &amp; that" line="5">
+    <failure message="this &amp; that"/>
+  </testcase>
+</testsuite>
+"#
+        );
+    }
+
+    #[test]
+    fn test_summary_at_prompt() {
+        let mut report_path = env::temp_dir();
+        report_path.push("rlox-interpreter-junit-prompt.xml");
+        let _ = fs::remove_file(&report_path);
+
+        // Assertions are reported after each line, as they would be after a script.
+        let mut child = std::process::Command::new(exe_path())
+            .args(["--test-summary", "--junit", report_path.to_str().unwrap()])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(b"assert 1 > 2;\nassert 1 < 2;\n")
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+
+        assert_eq!(output.status.code(), Some(71));
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "> 0 passed, 1 failed\n> 1 passed, 0 failed\n> "
+        );
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "Assertion failed: 1 > 2\n[line 1]\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&report_path).unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuite name="rlox" tests="2" failures="1">
+  <testcase name="1 &gt; 2" line="1">
+    <failure message="Assertion failed: 1 &gt; 2"/>
+  </testcase>
+  <testcase name="1 &lt; 2" line="1"/>
+</testsuite>
+"#
+        );
+    }
+
+    #[test]
+    fn test_lint_flag() {
+        let source = "class Animal {
+              init(name) { this.name = name; }
+              speak() { return \"...\"; }
+              describe() { return this.name; }
+            }
+            class Dog < Animal {
+              init(name) { super.init(name); }
+              speak() { return \"Woof\"; }
+              describe() { return \"Dog \" + super.describe(); }
+              fetch() { return \"ball\"; }
+            }
+            class Puppy < Dog {
+              fetch() { return \"stick\"; }
+              speak() { return super.speak() + \"!\"; }
+            }
+            print Puppy(\"Rex\").speak();";
+
+        // Overrides that call the method they override are intentional; the others warn
+        // without stopping the script.
+        let output = run_source_with_args("lint", source, &["--lint"]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "Woof!\n");
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "[line 8] Warning at 'speak': Method 'speak' overrides an inherited method without calling 'super.speak'.
+[line 13] Warning at 'fetch': Method 'fetch' overrides an inherited method without calling 'super.fetch'.
+"
+        );
+
+        let output = run_source("no-lint", source);
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+    }
+
+    #[test]
+    fn test_friendly_errors_flag() {
+        let cases = [
+            (
+                "var a = 1\nprint a;",
+                "[line 2] Error at 'print': Expect ';' after variable declaration. (did you forget a semicolon?)\n",
+                "[line 2] Error at 'print': Expect ';' after variable declaration.\n",
+            ),
+            (
+                "fun (a) { return a; }",
+                "[line 1] Error at '(': Expect function name, as in 'fun name() {}'.\n",
+                "[line 1] Error at '(': Expect function name.\n",
+            ),
+        ];
+        for (source, friendly, plain) in cases {
+            let output = run_args(&["--friendly-errors", "-e", source]);
+            assert_eq!(output.status.code(), Some(65));
+            assert!(
+                String::from_utf8(output.stderr)
+                    .unwrap()
+                    .starts_with(friendly),
+                "Unexpected errors for {}",
+                source
+            );
+
+            let output = run_args(&["-e", source]);
+            assert!(String::from_utf8(output.stderr).unwrap().starts_with(plain));
+        }
+
+        // Assigning in a condition is valid, so it only warns.
+        let source = "var a = 1;\nif (a = 2) print a;";
+        let output = run_args(&["--friendly-errors", "-e", source]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\n");
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "[line 2] Warning at 'a': Assignment used as a condition. Did you mean '=='?\n"
+        );
+
+        let output = run_args(&["-e", source]);
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+    }
+
+    #[test]
+    fn test_warn_uninit_flag() {
+        let source = "var a;
+            print a;
+            var b;
+            b = 1;
+            print b;
+            {
+              var c;
+              fun f() { return c; }
+              c = 2;
+              print f();
+            }
+            print a;";
+
+        // Only the first read of a variable that nothing was assigned to before it warns.
+        let output = run_source_with_args("warn-uninit", source, &["--warn-uninit"]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "nil\n1\n2\nnil\n"
+        );
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "[line 2] Warning at 'a': Variable 'a' is read before it is assigned.
+[line 8] Warning at 'c': Variable 'c' is read before it is assigned.
+"
+        );
+
+        let output = run_source("no-warn-uninit", source);
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+    }
+
+    #[test]
+    fn test_profile_flag() {
+        let mut profile_path = env::temp_dir();
+        profile_path.push("rlox-interpreter-profile.folded");
+        let _ = fs::remove_file(&profile_path);
+
+        let output = run_source_with_args(
+            "profile",
+            "fun leaf() { return 1; }
+            fun branch() { return leaf() + leaf(); }
+            print branch() + leaf();",
+            &["--profile", profile_path.to_str().unwrap()],
+        );
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n");
+
+        let profile = fs::read_to_string(&profile_path).unwrap();
+        let stacks: Vec<&str> = profile
+            .lines()
+            .map(|line| {
+                let (stack, nanos) = line.rsplit_once(' ').unwrap();
+                assert!(nanos.parse::<u64>().is_ok(), "{}", line);
+                stack
+            })
+            .collect();
+        assert_eq!(
+            stacks,
+            vec![
+                "<script>",
+                "<script>;branch",
+                "<script>;branch;leaf",
+                "<script>;leaf"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prompt_vars_command() {
+        let mut child = std::process::Command::new(exe_path())
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(b"var b = 2;\nvar a = \"one\";\nfun f() {}\nvar c = clock;\n:vars\n")
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+
+        // Natives are left out, even when a variable holds one.
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "> > > > > a = \"one\"\nb = 2\nf = <fn f/0>\n> "
+        );
+    }
+
+    #[test]
+    fn test_inline_source() {
+        let output = run_args(&["-e", "print 1 + 2;"]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n");
+
+        let output = run_args(&["-e", "print 1"]);
+        assert_eq!(output.status.code(), Some(65));
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "[line 1] Error at end: Expect ';' after value.\n"
+        );
+
+        let output = run_args(&["-e", "nil + 1;"]);
+        assert_eq!(output.status.code(), Some(70));
+
+        assert_eq!(run_args(&["-e"]).status.code(), Some(64));
+        assert_eq!(
+            run_args(&["-e", "1;", "script.lox"]).status.code(),
+            Some(64)
+        );
+    }
+
+    #[test]
+    fn test_repl_semantics_flag() {
+        let output = run_args(&["--repl-semantics", "-e", "print 1"]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+
+        // A final bare expression is printed, earlier ones are not.
+        let output = run_args(&["--repl-semantics", "-e", "var a = 2; a; a * 3"]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "6\n");
+
+        let output = run_source_with_args(
+            "repl-semantics",
+            "fun square(x) { return x * x; }\nsquare(4)\n",
+            &["--repl-semantics"],
+        );
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "16\n");
+
+        // The body of a loop or an if ending the script isn't a top-level expression, so it
+        // is run as usual instead of printed.
+        for source in [
+            "var i = 0; while (i < 3) i = i + 1",
+            "var i = 0; if (i < 3) i = i + 1",
+            "var i = 0; for (; i < 3;) i = i + 1",
+        ] {
+            let output = run_args(&["--repl-semantics", "-e", source]);
+            assert_eq!(output.status.code(), Some(0), "{}", source);
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), "", "{}", source);
+        }
+
+        // Only the last statement may leave out its semicolon.
+        let output = run_args(&["--repl-semantics", "-e", "print 1 print 2"]);
+        assert_eq!(output.status.code(), Some(65));
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "[line 1] Error at 'print': Expect ';' after value.\n"
+        );
+    }
+
+    #[test]
+    fn test_sorted_maps_flag() {
+        let source = "var map = {\"b\": 1, 10: 2, \"a\": 3, -1: 4};
+            map[2] = 5;
+            map[\"b\"] = 6;
+            print keys(map);
+            print values(map);
+            print entries(map);
+            print map;
+            prettyPrint({\"y\": 1, \"x\": {2: nil, 1: nil}});";
+
+        // Without the flag keys stay in the order they were added.
+        let output = run_source("sorted-maps-off", source);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "[\"b\", 10, \"a\", -1, 2]\n\
+             [6, 2, 3, 4, 5]\n\
+             [[\"b\", 6], [10, 2], [\"a\", 3], [-1, 4], [2, 5]]\n\
+             {\"b\": 6, 10: 2, \"a\": 3, -1: 4, 2: 5}\n\
+             {\n  \"y\": 1,\n  \"x\": {\n    2: nil,\n    1: nil\n  }\n}\n"
+        );
+
+        let output = run_source_with_args("sorted-maps", source, &["--sorted-maps"]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "[-1, 2, 10, \"a\", \"b\"]\n\
+             [4, 5, 2, 3, 6]\n\
+             [[-1, 4], [2, 5], [10, 2], [\"a\", 3], [\"b\", 6]]\n\
+             {-1: 4, 2: 5, 10: 2, \"a\": 3, \"b\": 6}\n\
+             {\n  \"x\": {\n    1: nil,\n    2: nil\n  },\n  \"y\": 1\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_strict_bool_flag() {
+        let source = "var n = 0;
+            if (n == 0) print \"zero\";
+            while (n < 2) n = n + 1;
+            print n > 1 and !false;
+            if (n) print \"truthy\";";
+
+        // Without the flag every value can be used as a condition.
+        let output = run_source("strict-bool-off", source);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "zero\ntrue\ntruthy\n"
+        );
+
+        let output = run_source_with_args("strict-bool", source, &["--strict-bool"]);
+        assert_eq!(output.status.code(), Some(70));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "zero\ntrue\n");
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "Condition must be a boolean.\n[line 5]\n"
+        );
+
+        for (source, error) in [
+            ("while (nil) {}", "Condition must be a boolean."),
+            ("for (;1;) {}", "Condition must be a boolean."),
+            ("print \"a\" or true;", "Condition must be a boolean."),
+            ("print nil and true;", "Condition must be a boolean."),
+            ("assert 1;", "Condition must be a boolean."),
+            ("print !nil;", "Operand must be a boolean."),
+        ] {
+            let output = run_args(&["--strict-bool", "-e", source]);
+            assert_eq!(output.status.code(), Some(70), "{}", source);
+            assert_eq!(
+                String::from_utf8(output.stderr).unwrap(),
+                format!("{}\n[line 1]\n", error),
+                "{}",
+                source
+            );
+        }
+
+        // Normally 'and' and 'or' return one of their operands, in strict mode they return a
+        // boolean and reject other operands on either side.
+        let output = run_args(&["-e", "print 1 and 2; print nil or \"b\";"]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\nb\n");
+
+        let output = run_args(&[
+            "--strict-bool",
+            "-e",
+            "print true and false; print false or true;",
+        ]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "false\ntrue\n");
+
+        for source in ["print 1 and 2;", "print true and 2;", "print false or nil;"] {
+            let output = run_args(&["--strict-bool", "-e", source]);
+            assert_eq!(output.status.code(), Some(70), "{}", source);
+            assert_eq!(
+                String::from_utf8(output.stderr).unwrap(),
+                "Condition must be a boolean.\n[line 1]\n",
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_dump_tokens_flag() {
+        let output = run_args(&["--dump-tokens", "-e", "a ? b :\n\"c\";"]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "   1 Identifier 'a'
+   1 Question '?'
+   1 Identifier 'b'
+   1 Colon ':'
+   2 String '\"c\"'
+   2 Semicolon ';'
+   2 Eof '<EOF>'
+"
+        );
+
+        // The same tokens parse as a conditional expression.
+        let output = run_args(&["-e", "print true ? \"b\" : \"c\";"]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "b\n");
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let source = "// comment\nprint \"multi\nline\";\nprint 1 +;\n";
+
+        for (name, line_ending) in [("lf", "\n"), ("crlf", "\r\n"), ("cr", "\r")] {
+            let output = run_source(
+                &format!("line-endings-{}", name),
+                &source.replace('\n', line_ending),
+            );
+            assert_eq!(output.status.code(), Some(65), "{}", name);
+            assert_eq!(
+                String::from_utf8(output.stderr).unwrap(),
+                "[line 4] Error at ';': Expect expression.\n",
+                "{}",
+                name
+            );
         }
     }
 }
@@ -0,0 +1,80 @@
+// Number of significant digits printf's %g uses when no precision is given.
+const G_PRECISION: i32 = 6;
+
+// Formats a number like clox's printf("%g", value), so that output matches the reference
+// implementation: six significant digits, exponent notation for very large or small
+// magnitudes and no trailing zeros.
+pub fn format_g(num: f64) -> String {
+    if num.is_nan() {
+        return String::from("nan");
+    }
+    if num.is_infinite() {
+        return String::from(if num > 0.0 { "inf" } else { "-inf" });
+    }
+    if num == 0.0 {
+        return String::from(if num.is_sign_negative() { "-0" } else { "0" });
+    }
+
+    let (mantissa, exponent) = split_exponent(num, G_PRECISION as usize);
+
+    if !(-4..G_PRECISION).contains(&exponent) {
+        format!(
+            "{}e{}{:02}",
+            trim_fraction_zeros(mantissa),
+            if exponent < 0 { '-' } else { '+' },
+            exponent.abs()
+        )
+    } else {
+        let decimals = (G_PRECISION - 1 - exponent) as usize;
+        trim_fraction_zeros(format!("{:.*}", decimals, num))
+    }
+}
+
+// Formats a finite, non-zero number with the given count of significant digits, never
+// switching to exponent notation.
+pub fn format_significant(num: f64, precision: usize) -> String {
+    // Rounding through the exponent form also yields the exponent after rounding, so
+    // 9.99 with two digits becomes 10 rather than 10.0.
+    let (mantissa, exponent) = split_exponent(num, precision);
+    let rounded: f64 = format!("{}e{}", mantissa, exponent).parse().unwrap();
+    let decimals = (precision as i32 - 1 - exponent).max(0) as usize;
+
+    trim_fraction_zeros(format!("{:.*}", decimals, rounded))
+}
+
+// Formats a number like printf("%.*e"), with the given count of digits after the
+// decimal point and at least two exponent digits.
+pub fn format_exponential(num: f64, digits: usize) -> String {
+    if !num.is_finite() {
+        return format!("{}", num);
+    }
+
+    let scientific = format!("{:.*e}", digits, num);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    format!(
+        "{}e{}{:02}",
+        mantissa,
+        if exponent < 0 { '-' } else { '+' },
+        exponent.abs()
+    )
+}
+
+// Rounds to the given count of significant digits and splits the result into its
+// mantissa and decimal exponent.
+fn split_exponent(num: f64, precision: usize) -> (String, i32) {
+    let scientific = format!("{:.*e}", precision - 1, num);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    (String::from(mantissa), exponent.parse().unwrap())
+}
+
+fn trim_fraction_zeros(formatted: String) -> String {
+    if formatted.contains('.') {
+        formatted
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string()
+    } else {
+        formatted
+    }
+}
@@ -1,22 +1,33 @@
-use crate::ast::{Expr, ExprId, ExprVariable, Stmt, StmtFunction};
+use crate::ast::{Expr, ExprVariable, Stmt, StmtFunction};
 use crate::scanner::{Token, TokenLiteral, TokenType};
 use crate::App;
 use std::rc::Rc;
 
+// How deeply expressions and statements may nest. Parsing, resolving and interpreting all
+// recurse once per level, so deeper source would overflow the stack.
+const MAX_DEPTH: usize = 256;
+
 pub struct Parser<'a> {
     tokens: Vec<Token>,
     current: usize,
     app: &'a App,
-    expr_id_count: usize,
+    // Constructs currently being parsed, innermost last, used to give errors more context.
+    contexts: Vec<&'static str>,
+    // Number of enclosing brackets, inside which newlines never end a statement.
+    nesting: usize,
+    // Number of expressions and statements being parsed, which is limited to MAX_DEPTH.
+    depth: usize,
 }
 
 impl Parser<'_> {
-    pub fn new(app: &App, tokens: Vec<Token>) -> Parser {
+    pub fn new(app: &App, tokens: Vec<Token>) -> Parser<'_> {
         Parser {
             tokens,
             current: 0,
             app,
-            expr_id_count: 0,
+            contexts: Vec::new(),
+            nesting: 0,
+            depth: 0,
         }
     }
 
@@ -29,15 +40,33 @@ impl Parser<'_> {
             }
         }
 
+        // A bare expression ending the script without a semicolon is printed, as the REPL
+        // would show its value. Only a top-level statement counts, not the body of a loop or
+        // an if that ends the script. Errors of the implicit print are reported at the
+        // expression's last token.
+        if self.app.repl_semantics {
+            if let Some(Stmt::Expression(_)) = statements.last() {
+                let keyword = self.previous_token().clone();
+                if keyword.token_type != TokenType::Semicolon {
+                    if let Some(Stmt::Expression(expression)) = statements.pop() {
+                        statements.push(Stmt::Print {
+                            keyword,
+                            expression,
+                        });
+                    }
+                }
+            }
+        }
+
         statements
     }
 
     fn expression(&mut self) -> Option<Expr> {
-        self.assignment()
+        self.deeper(Self::assignment)
     }
 
     fn assignment(&mut self) -> Option<Expr> {
-        let expr = self.or()?;
+        let expr = self.conditional()?;
 
         if self.match_one_of([TokenType::Equal]) {
             let equals = self.previous_token().clone();
@@ -47,15 +76,50 @@ impl Parser<'_> {
                 Expr::Variable(_, ExprVariable { name }) => Some(Expr::Assign {
                     name,
                     value: Box::from(value),
-                    id: self.gen_expr_id(),
+                    id: self.app.next_expr_id(),
+                }),
+                Expr::Global { name, value: None } => Some(Expr::Global {
+                    name,
+                    value: Some(Box::new(value)),
                 }),
-                Expr::Get { object, name } => Some(Expr::Set {
+                Expr::Get { object, name, .. } => Some(Expr::Set {
                     object,
                     name,
                     value: Box::new(value),
                 }),
+                Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                } => Some(Expr::IndexSet {
+                    object,
+                    bracket,
+                    index,
+                    value: Box::new(value),
+                }),
+                Expr::This { .. } => {
+                    self.error(
+                        &equals,
+                        "Invalid assignment target. Can't assign to 'this'.",
+                    );
+                    None
+                }
+                Expr::Super { .. } => {
+                    self.error(
+                        &equals,
+                        "Invalid assignment target. Can't assign to a superclass method.",
+                    );
+                    None
+                }
+                Expr::Call { .. } => {
+                    self.error(
+                        &equals,
+                        "Invalid assignment target. Can't assign to the result of a call.",
+                    );
+                    None
+                }
                 _ => {
-                    self.app.error_token(&equals, "Invalid assignment target.");
+                    self.error(&equals, "Invalid assignment target.");
                     None
                 }
             };
@@ -64,6 +128,29 @@ impl Parser<'_> {
         Some(expr)
     }
 
+    fn conditional(&mut self) -> Option<Expr> {
+        let condition = self.or()?;
+
+        if self.match_one_of([TokenType::Question]) {
+            let question = self.previous_token().clone();
+            let then_branch = self.expression()?;
+            self.consume(
+                TokenType::Colon,
+                "Expect ':' after then branch of conditional expression.",
+            )?;
+            // Recursing makes the operator right-associative.
+            let else_branch = self.conditional()?;
+            return Some(Expr::Ternary {
+                condition: Box::new(condition),
+                question,
+                then_branch: Box::new(then_branch),
+                else_branch: Box::new(else_branch),
+            });
+        }
+
+        Some(condition)
+    }
+
     fn or(&mut self) -> Option<Expr> {
         let mut expr = self.and();
 
@@ -146,7 +233,8 @@ impl Parser<'_> {
 
         expr.as_ref()?;
 
-        while self.match_one_of([TokenType::Minus, TokenType::Plus]) {
+        while !self.starts_new_statement() && self.match_one_of([TokenType::Minus, TokenType::Plus])
+        {
             let operator = self.previous_token().clone();
             let right = self.factor()?;
             expr = Some(Expr::Binary {
@@ -180,11 +268,18 @@ impl Parser<'_> {
     fn unary(&mut self) -> Option<Expr> {
         if self.match_one_of([TokenType::Bang, TokenType::Minus]) {
             let operator = self.previous_token().clone();
-            let right = self.unary()?;
+            let right = self.deeper(Self::unary)?;
             Some(Expr::Unary {
                 operator,
                 right: Box::new(right),
             })
+        } else if self.match_one_of([TokenType::TypeOf]) {
+            let keyword = self.previous_token().clone();
+            let operand = self.deeper(Self::unary)?;
+            Some(Expr::TypeOf {
+                keyword,
+                operand: Box::new(operand),
+            })
         } else {
             self.call()
         }
@@ -196,14 +291,25 @@ impl Parser<'_> {
         expr.as_ref()?;
 
         loop {
-            if self.match_one_of([TokenType::LeftParen]) {
-                expr = self.finish_call(expr?);
+            if self.starts_new_statement() {
+                break;
+            } else if self.match_one_of([TokenType::LeftParen]) {
+                expr = self.nested(|parser| parser.finish_call(expr?));
             } else if self.match_one_of([TokenType::Dot]) {
                 let name =
                     self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
                 expr = Some(Expr::Get {
                     object: Box::new(expr?.clone()),
                     name,
+                    id: self.app.next_expr_id(),
+                })
+            } else if self.match_one_of([TokenType::LeftBracket]) {
+                let index = self.nested(Self::expression)?;
+                let bracket = self.consume(TokenType::RightBracket, "Expect ']' after index.")?;
+                expr = Some(Expr::Index {
+                    object: Box::new(expr?),
+                    bracket,
+                    index: Box::new(index),
                 })
             } else {
                 break;
@@ -213,18 +319,59 @@ impl Parser<'_> {
         expr
     }
 
+    fn list(&mut self) -> Option<Expr> {
+        let mut elements = Vec::new();
+
+        if !self.check_token(TokenType::RightBracket) {
+            loop {
+                elements.push(self.with_context("list literal", Self::expression)?);
+                // A trailing comma is allowed.
+                if !self.match_one_of([TokenType::Comma])
+                    || self.check_token(TokenType::RightBracket)
+                {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightBracket, "Expect ']' after list elements.")?;
+        Some(Expr::List { elements })
+    }
+
+    fn map(&mut self) -> Option<Expr> {
+        let brace = self.previous_token().clone();
+        let mut entries = Vec::new();
+
+        if !self.check_token(TokenType::RightBrace) {
+            loop {
+                let key = self.with_context("map literal", Self::expression)?;
+                self.consume(TokenType::Colon, "Expect ':' after map key.")?;
+                let value = self.with_context("map literal", Self::expression)?;
+                entries.push((key, value));
+                // A trailing comma is allowed.
+                if !self.match_one_of([TokenType::Comma]) || self.check_token(TokenType::RightBrace)
+                {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after map entries.")?;
+        Some(Expr::Map { brace, entries })
+    }
+
     fn finish_call(&mut self, callee: Expr) -> Option<Expr> {
         let mut arguments = Vec::new();
 
         if !self.check_token(TokenType::RightParen) {
             loop {
                 if arguments.len() >= 255 {
-                    self.app.error_token(
+                    self.error(
                         &self.peek_token().clone(),
                         "Can't have more than 255 arguments.",
                     );
                 }
-                arguments.push(self.expression()?);
+                arguments.push(self.with_context("call arguments", Self::expression)?);
                 if !self.match_one_of([TokenType::Comma]) {
                     break;
                 }
@@ -259,55 +406,137 @@ impl Parser<'_> {
             })
         } else if self.match_one_of([TokenType::Identifier]) {
             Some(Expr::Variable(
-                self.gen_expr_id(),
+                self.app.next_expr_id(),
                 ExprVariable {
                     name: self.previous_token().clone(),
                 },
             ))
+        } else if self.match_one_of([TokenType::Global]) {
+            let name = self.consume(
+                TokenType::Identifier,
+                "Expect variable name after 'global'.",
+            )?;
+            Some(Expr::Global { name, value: None })
         } else if self.match_one_of([TokenType::Super]) {
             let keyword = self.previous_token().clone();
-            self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
-            let method = self.consume(TokenType::Identifier, "Expect superclass method name.")?;
+            // 'super(...)' is shorthand for 'super.init(...)'.
+            let method = if self.check_token(TokenType::LeftParen) {
+                Token {
+                    token_type: TokenType::Identifier,
+                    lexeme: self.app.interner.sym_init,
+                    literal: TokenLiteral::Nil,
+                    line: keyword.line,
+                    start: keyword.start,
+                    len: 0,
+                }
+            } else {
+                self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+                self.consume(TokenType::Identifier, "Expect superclass method name.")?
+            };
             Some(Expr::Super {
                 keyword,
                 method,
-                id: self.gen_expr_id(),
+                id: self.app.next_expr_id(),
             })
         } else if self.match_one_of([TokenType::This]) {
             Some(Expr::This {
                 keyword: self.previous_token().clone(),
-                id: self.gen_expr_id(),
+                id: self.app.next_expr_id(),
             })
         } else if self.match_one_of([TokenType::LeftParen]) {
-            let expr = self.expression()?;
+            let expr = self.nested(Self::expression)?;
             self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
             Some(Expr::Grouping {
                 expression: Box::new(expr),
             })
+        } else if self.match_one_of([TokenType::LeftBracket]) {
+            self.nested(Self::list)
+        } else if self.match_one_of([TokenType::LeftBrace]) {
+            self.nested(Self::map)
+        } else if self.match_one_of([TokenType::Match]) {
+            self.nested(|parser| parser.with_context("match expression", Self::match_expression))
         } else {
-            self.app
-                .error_token(&self.peek_token().clone(), "Expect expression.");
+            self.error(&self.peek_token().clone(), "Expect expression.");
             None
         }
     }
 
-    fn gen_expr_id(&mut self) -> ExprId {
-        let id = ExprId(self.expr_id_count);
-        self.expr_id_count += 1;
-        id
+    fn match_expression(&mut self) -> Option<Expr> {
+        let keyword = self.previous_token().clone();
+
+        self.consume(TokenType::LeftParen, "Expect '(' after 'match'.")?;
+        let subject = self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after match subject.")?;
+
+        self.consume(TokenType::LeftBrace, "Expect '{' before match arms.")?;
+
+        let mut arms = Vec::new();
+        let mut default = None;
+        if !self.check_token(TokenType::RightBrace) {
+            loop {
+                if self.match_one_of([TokenType::Else]) {
+                    self.consume(TokenType::Arrow, "Expect '=>' after 'else'.")?;
+                    default = Some(Box::new(self.expression()?));
+                    self.match_one_of([TokenType::Comma]);
+                    break;
+                }
+
+                let value = self.expression()?;
+                self.consume(TokenType::Arrow, "Expect '=>' after match arm value.")?;
+                let result = self.expression()?;
+                arms.push((value, result));
+
+                // A trailing comma is allowed.
+                if !self.match_one_of([TokenType::Comma]) || self.check_token(TokenType::RightBrace)
+                {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after match arms.")?;
+
+        Some(Expr::Match {
+            keyword,
+            subject: Box::new(subject),
+            arms,
+            default,
+        })
     }
 
     fn statement(&mut self) -> Option<Stmt> {
-        if self.match_one_of([TokenType::For]) {
+        if self.match_one_of([TokenType::Assert]) {
+            self.assert_statement()
+        } else if self.match_one_of([TokenType::Break]) {
+            let keyword = self.previous_token().clone();
+            self.consume_terminator("Expect ';' after 'break'.")?;
+            Some(Stmt::Break { keyword })
+        } else if self.match_one_of([TokenType::Continue]) {
+            let keyword = self.previous_token().clone();
+            self.consume_terminator("Expect ';' after 'continue'.")?;
+            Some(Stmt::Continue { keyword })
+        } else if self.match_one_of([TokenType::For]) {
             self.for_statement()
+        } else if self.match_one_of([TokenType::Foreach]) {
+            self.foreach_statement()
         } else if self.match_one_of([TokenType::If]) {
             self.if_statement()
+        } else if self.match_one_of([TokenType::Import]) {
+            self.import_statement()
         } else if self.match_one_of([TokenType::Print]) {
             self.print_statement()
+        } else if self.match_one_of([TokenType::Repeat]) {
+            self.repeat_statement()
         } else if self.match_one_of([TokenType::Return]) {
             self.return_statement()
+        } else if self.match_one_of([TokenType::Throw]) {
+            self.throw_statement()
+        } else if self.match_one_of([TokenType::Try]) {
+            self.try_statement()
         } else if self.match_one_of([TokenType::While]) {
             self.while_statement()
+        } else if self.match_one_of([TokenType::Yield]) {
+            self.yield_statement()
         } else if self.match_one_of([TokenType::LeftBrace]) {
             Some(Stmt::Block {
                 statements: self.block()?,
@@ -317,6 +546,19 @@ impl Parser<'_> {
         }
     }
 
+    fn assert_statement(&mut self) -> Option<Stmt> {
+        let keyword = self.previous_token().clone();
+        let start = self.current;
+        let condition = self.expression()?;
+        let source = self.source_text(start, self.current);
+        self.consume_terminator("Expect ';' after assertion.")?;
+        Some(Stmt::Assert {
+            keyword,
+            condition,
+            source,
+        })
+    }
+
     fn block(&mut self) -> Option<Vec<Stmt>> {
         let mut statements = Vec::new();
 
@@ -332,121 +574,250 @@ impl Parser<'_> {
     }
 
     fn for_statement(&mut self) -> Option<Stmt> {
+        let keyword = self.previous_token().clone();
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
 
-        let initializer = if self.match_one_of([TokenType::Semicolon]) {
-            None
-        } else if self.match_one_of([TokenType::Var]) {
-            Some(self.var_declaration()?)
-        } else {
-            Some(self.expression_statement()?)
-        };
-
-        let condition = if !self.check_token(TokenType::Semicolon) {
-            Some(self.expression()?)
-        } else {
-            None
-        };
+        let (initializer, condition, increment) = self.nested(|parser| {
+            parser.with_context("for clauses", |parser| {
+                let initializer = if parser.match_one_of([TokenType::Semicolon]) {
+                    Vec::new()
+                } else if parser.match_one_of([TokenType::Var]) {
+                    vec![parser.var_declaration()?]
+                } else {
+                    let expressions = parser.expression_list()?;
+                    parser.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+                    expressions.into_iter().map(Stmt::Expression).collect()
+                };
+
+                let condition = if !parser.check_token(TokenType::Semicolon) {
+                    Some(parser.expression()?)
+                } else {
+                    None
+                };
 
-        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+                parser.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
 
-        let increment = if !self.check_token(TokenType::RightParen) {
-            Some(self.expression()?)
-        } else {
-            None
-        };
+                let increment = if !parser.check_token(TokenType::RightParen) {
+                    parser.expression_list()?
+                } else {
+                    Vec::new()
+                };
 
-        self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+                parser.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
 
-        let mut body = self.statement()?;
+                Some((initializer, condition, increment))
+            })
+        })?;
 
-        if let Some(increment) = increment {
-            body = Stmt::Block {
-                statements: vec![body, Stmt::Expression(increment)],
-            };
-        };
+        let body = self.deeper(Self::statement)?;
 
-        body = Stmt::While {
+        // The increment stays separate from the body so that leaving the body early
+        // still advances the loop.
+        let mut body = Stmt::While {
+            keyword,
             condition: condition.unwrap_or(Expr::Literal {
                 value: TokenLiteral::Bool(true),
             }),
             body: Box::new(body),
+            increment,
         };
 
-        if let Some(initializer) = initializer {
-            body = Stmt::Block {
-                statements: vec![initializer, body],
-            };
+        if !initializer.is_empty() {
+            let mut statements = initializer;
+            statements.push(body);
+            body = Stmt::Block { statements };
         }
 
         Some(body)
     }
 
+    fn foreach_statement(&mut self) -> Option<Stmt> {
+        let keyword = self.previous_token().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'foreach'.")?;
+        let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+
+        // 'in' is only special here, so it stays usable as an identifier elsewhere.
+        let in_token = self.consume(TokenType::Identifier, "Expect 'in' after variable name.")?;
+        if self.app.interner.resolve(in_token.lexeme) != "in" {
+            self.error(&in_token, "Expect 'in' after variable name.");
+            return None;
+        }
+
+        let iterable =
+            self.nested(|parser| parser.with_context("foreach clause", Self::expression))?;
+        self.consume(TokenType::RightParen, "Expect ')' after foreach clause.")?;
+        let body = self.deeper(Self::statement)?;
+
+        Some(Stmt::Foreach {
+            keyword,
+            name,
+            iterable,
+            body: Box::new(body),
+        })
+    }
+
+    // Comma-separated expressions, only allowed in the initializer and increment clauses of
+    // a for loop so that they don't clash with argument lists.
+    fn expression_list(&mut self) -> Option<Vec<Expr>> {
+        let mut expressions = vec![self.expression()?];
+        while self.match_one_of([TokenType::Comma]) {
+            expressions.push(self.expression()?);
+        }
+        Some(expressions)
+    }
+
     fn if_statement(&mut self) -> Option<Stmt> {
+        let keyword = self.previous_token().clone();
         self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
-        let condition = self.expression()?;
+        let condition =
+            self.nested(|parser| parser.with_context("if condition", Self::expression))?;
         self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
+        if let (true, Expr::Assign { name, .. }) = (self.app.friendly_errors, &condition) {
+            self.app
+                .warning_token(name, "Assignment used as a condition. Did you mean '=='?");
+        }
 
-        let then_branch = self.statement()?;
+        let then_branch = self.deeper(Self::statement)?;
         let mut else_branch = None;
         if self.match_one_of([TokenType::Else]) {
-            else_branch = Some(Box::from(self.statement()?));
+            else_branch = Some(Box::from(self.deeper(Self::statement)?));
         }
 
         Some(Stmt::If {
+            keyword,
             condition,
             then_branch: Box::new(then_branch),
             else_branch,
         })
     }
 
+    fn import_statement(&mut self) -> Option<Stmt> {
+        self.import_rest(None)
+    }
+
+    // Parses the rest of an import after the 'import' keyword, which `name` is set for when it
+    // initializes a variable.
+    fn import_rest(&mut self, name: Option<Token>) -> Option<Stmt> {
+        let keyword = self.previous_token().clone();
+        let path = self.consume(TokenType::String, "Expect path string after 'import'.")?;
+        self.consume_terminator("Expect ';' after import path.")?;
+
+        match path.literal {
+            TokenLiteral::String(path) => Some(Stmt::Import {
+                keyword,
+                path,
+                name,
+            }),
+            _ => unreachable!("String token without a string literal"),
+        }
+    }
+
     fn print_statement(&mut self) -> Option<Stmt> {
+        let keyword = self.previous_token().clone();
         let expression = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
-        Some(Stmt::Print { expression })
+        self.consume_terminator("Expect ';' after value.")?;
+        Some(Stmt::Print {
+            keyword,
+            expression,
+        })
+    }
+
+    fn repeat_statement(&mut self) -> Option<Stmt> {
+        let keyword = self.previous_token().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'repeat'.")?;
+        let count = self.nested(|parser| parser.with_context("repeat count", Self::expression))?;
+        self.consume(TokenType::RightParen, "Expect ')' after repeat count.")?;
+        let body = self.deeper(Self::statement)?;
+
+        Some(Stmt::Repeat {
+            keyword,
+            count,
+            body: Box::new(body),
+        })
+    }
+
+    fn throw_statement(&mut self) -> Option<Stmt> {
+        let keyword = self.previous_token().clone();
+        let value = self.expression()?;
+        self.consume_terminator("Expect ';' after thrown value.")?;
+        Some(Stmt::Throw { keyword, value })
+    }
+
+    fn try_statement(&mut self) -> Option<Stmt> {
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'.")?;
+        let body = self.block()?;
+        self.consume(TokenType::Catch, "Expect 'catch' after try block.")?;
+        self.consume(TokenType::LeftParen, "Expect '(' after 'catch'.")?;
+        let catch_var = self.consume(TokenType::Identifier, "Expect error variable name.")?;
+        self.consume(TokenType::RightParen, "Expect ')' after error variable.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before catch body.")?;
+        let catch_body = self.block()?;
+
+        Some(Stmt::Try {
+            body,
+            catch_var,
+            catch_body,
+        })
     }
 
     fn return_statement(&mut self) -> Option<Stmt> {
         let keyword = self.previous_token().clone();
         let mut value = None;
-        if !self.check_token(TokenType::Semicolon) {
+        if !self.check_token(TokenType::Semicolon) && !self.at_implicit_terminator() {
             value = Some(self.expression()?);
         }
 
-        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        self.consume_terminator("Expect ';' after return value.")?;
 
         Some(Stmt::Return { keyword, value })
     }
 
+    fn yield_statement(&mut self) -> Option<Stmt> {
+        let keyword = self.previous_token().clone();
+        let value = self.expression()?;
+        self.consume_terminator("Expect ';' after yield value.")?;
+        Some(Stmt::Yield { keyword, value })
+    }
+
     fn while_statement(&mut self) -> Option<Stmt> {
+        let keyword = self.previous_token().clone();
         self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
-        let condition = self.expression()?;
+        let condition =
+            self.nested(|parser| parser.with_context("while condition", Self::expression))?;
         self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
-        let body = self.statement()?;
+        let body = self.deeper(Self::statement)?;
 
         Some(Stmt::While {
+            keyword,
             condition,
             body: Box::from(body),
+            increment: Vec::new(),
         })
     }
 
     fn expression_statement(&mut self) -> Option<Stmt> {
         let expression = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+        self.consume_terminator("Expect ';' after expression.")?;
         Some(Stmt::Expression(expression))
     }
 
     fn declaration(&mut self) -> Option<Stmt> {
-        let declaration = if self.match_one_of([TokenType::Class]) {
-            self.class_declaration()
-        } else if self.match_one_of([TokenType::Fun]) {
-            self.function("function")
-                .map(|f| Stmt::Function(Rc::new(f)))
-        } else if self.match_one_of([TokenType::Var]) {
-            self.var_declaration()
-        } else {
-            self.statement()
-        };
+        let declaration = self.deeper(|parser| {
+            if parser.match_one_of([TokenType::Class]) {
+                parser.class_declaration()
+            } else if parser.match_one_of([TokenType::Enum]) {
+                parser.enum_declaration()
+            } else if parser.match_one_of([TokenType::Fun]) {
+                let is_generator = parser.match_one_of([TokenType::Star]);
+                parser
+                    .function("function")
+                    .map(|f| Stmt::Function(Rc::new(StmtFunction { is_generator, ..f })))
+            } else if parser.match_one_of([TokenType::Var]) {
+                parser.var_declaration()
+            } else {
+                parser.statement()
+            }
+        });
 
         if declaration.is_none() {
             self.synchronize()
@@ -461,7 +832,7 @@ impl Parser<'_> {
         let superclass = if self.match_one_of([TokenType::Less]) {
             self.consume(TokenType::Identifier, "Expect superclass name.")?;
             Some(Expr::Variable(
-                self.gen_expr_id(),
+                self.app.next_expr_id(),
                 ExprVariable {
                     name: self.previous_token().clone(),
                 },
@@ -470,24 +841,106 @@ impl Parser<'_> {
             None
         };
 
+        // Like 'in' for foreach, 'with' is only special here.
+        let mut mixins = Vec::new();
+        if self.check_token(TokenType::Identifier)
+            && self.app.interner.resolve(self.peek_token().lexeme) == "with"
+        {
+            self.advance();
+            loop {
+                let name = self.consume(TokenType::Identifier, "Expect mixin name.")?;
+                mixins.push(Expr::Variable(
+                    self.app.next_expr_id(),
+                    ExprVariable { name },
+                ));
+                if !self.match_one_of([TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+
         self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
 
-        let mut methods = Vec::new();
-        while !self.check_token(TokenType::RightBrace) && !self.is_at_end() {
-            methods.push(Rc::new(self.function("method")?));
-        }
+        let (fields, methods) = self.with_context("class body", |parser| {
+            let mut fields = Vec::new();
+            let mut methods = Vec::new();
+            while !parser.check_token(TokenType::RightBrace) && !parser.is_at_end() {
+                if parser.match_one_of([TokenType::Var]) {
+                    parser.field_declaration(&mut fields)?;
+                } else {
+                    methods.push(Rc::new(parser.function("method")?));
+                }
+            }
 
-        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+            parser.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+
+            Some((fields, methods))
+        })?;
 
         Some(Stmt::Class {
             name,
             superclass,
+            mixins,
+            fields,
             methods,
         })
     }
 
+    fn field_declaration(&mut self, fields: &mut Vec<(Token, Option<Expr>)>) -> Option<()> {
+        loop {
+            let name = self.consume(TokenType::Identifier, "Expect field name.")?;
+
+            let mut initializer = None;
+            if self.match_one_of([TokenType::Equal]) {
+                initializer = Some(self.expression()?);
+            }
+
+            fields.push((name, initializer));
+
+            if !self.match_one_of([TokenType::Comma]) {
+                break;
+            }
+        }
+
+        self.consume_terminator("Expect ';' after field declaration.")
+    }
+
+    fn enum_declaration(&mut self) -> Option<Stmt> {
+        let name = self.consume(TokenType::Identifier, "Expect enum name.")?;
+
+        self.consume(TokenType::LeftBrace, "Expect '{' before enum body.")?;
+
+        let members = self.with_context("enum body", |parser| {
+            let mut members = Vec::new();
+            if !parser.check_token(TokenType::RightBrace) {
+                loop {
+                    members.push(parser.consume(TokenType::Identifier, "Expect member name.")?);
+                    if !parser.match_one_of([TokenType::Comma]) {
+                        break;
+                    }
+                }
+            }
+
+            parser.consume(TokenType::RightBrace, "Expect '}' after enum members.")?;
+
+            Some(members)
+        })?;
+
+        Some(Stmt::Enum { name, members })
+    }
+
     fn function(&mut self, kind: &str) -> Option<StmtFunction> {
-        let name = self.consume(TokenType::Identifier, &format!("Expect {} name.", kind))?;
+        let name = if self.app.friendly_errors
+            && kind == "function"
+            && self.check_token(TokenType::LeftParen)
+        {
+            self.consume(
+                TokenType::Identifier,
+                "Expect function name, as in 'fun name() {}'.",
+            )?
+        } else {
+            self.consume(TokenType::Identifier, &format!("Expect {} name.", kind))?
+        };
 
         self.consume(
             TokenType::LeftParen,
@@ -498,7 +951,7 @@ impl Parser<'_> {
         if !self.check_token(TokenType::RightParen) {
             loop {
                 if params.len() >= 255 {
-                    self.app.error_token(
+                    self.error(
                         &self.peek_token().clone(),
                         "Can't have more than 255 parameters.",
                     );
@@ -514,63 +967,202 @@ impl Parser<'_> {
 
         self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
 
+        // 'fun name(params) => expr;' is shorthand for a body that returns expr.
+        if self.match_one_of([TokenType::Arrow]) {
+            let keyword = self.previous_token().clone();
+            let value = self.expression()?;
+            self.consume_terminator(&format!("Expect ';' after {} body.", kind))?;
+
+            return Some(StmtFunction {
+                name,
+                params,
+                body: vec![Stmt::Return {
+                    keyword,
+                    value: Some(value),
+                }],
+                is_generator: false,
+            });
+        }
+
         self.consume(
             TokenType::LeftBrace,
             &format!("Expect '{{' before {} body.", kind),
         )?;
 
-        let body = self.block()?;
+        let context = if kind == "method" {
+            "method body"
+        } else {
+            "function body"
+        };
+        let body = self.with_context(context, Self::block)?;
 
-        Some(StmtFunction { name, params, body })
+        Some(StmtFunction {
+            name,
+            params,
+            body,
+            is_generator: false,
+        })
     }
 
     fn var_declaration(&mut self) -> Option<Stmt> {
-        let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+        let mut declarations = Vec::new();
 
-        let mut initializer = None;
-        if self.match_one_of([TokenType::Equal]) {
-            initializer = Some(self.expression()?);
+        loop {
+            let name = self.consume(TokenType::Identifier, "Expect variable name.")?;
+
+            let mut initializer = None;
+            if self.match_one_of([TokenType::Equal]) {
+                // 'var name = import "path";' imports the script as a module.
+                if declarations.is_empty() && self.match_one_of([TokenType::Import]) {
+                    return self.import_rest(Some(name));
+                }
+                initializer = Some(self.expression()?);
+            }
+
+            declarations.push(Stmt::Var { name, initializer });
+
+            if !self.match_one_of([TokenType::Comma]) {
+                break;
+            }
         }
 
-        self.consume(
-            TokenType::Semicolon,
-            "Expect ';' after variable declaration.",
-        )?;
+        self.consume_terminator("Expect ';' after variable declaration.")?;
 
-        Some(Stmt::Var { name, initializer })
+        if declarations.len() == 1 {
+            declarations.pop()
+        } else {
+            Some(Stmt::VarList { declarations })
+        }
     }
 
     fn consume(&mut self, token_type: TokenType, message: &str) -> Option<Token> {
         if self.check_token(token_type) {
             Some(self.advance().clone())
         } else {
-            self.app.error_token(&self.peek_token().clone(), message);
+            self.error(&self.peek_token().clone(), message);
+            None
+        }
+    }
+
+    fn with_context<T>(
+        &mut self,
+        context: &'static str,
+        parse: impl FnOnce(&mut Self) -> Option<T>,
+    ) -> Option<T> {
+        self.contexts.push(context);
+        let result = parse(self);
+        self.contexts.pop();
+        result
+    }
+
+    fn deeper<T>(&mut self, parse: impl FnOnce(&mut Self) -> Option<T>) -> Option<T> {
+        if self.depth >= MAX_DEPTH {
+            self.error(&self.peek_token().clone(), "Too much nesting.");
+            return None;
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Option<T>) -> Option<T> {
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
+
+    fn on_new_line(&self) -> bool {
+        self.peek_token().line > self.previous_token().line
+    }
+
+    // With newline-terminated statements, a statement may also end at a line break or
+    // right before a '}' or the end of the file.
+    fn at_implicit_terminator(&self) -> bool {
+        self.app.newline_terminated
+            && (self.on_new_line() || self.check_token(TokenType::RightBrace) || self.is_at_end())
+            || self.at_script_end()
+    }
+
+    // With REPL semantics, the statement ending the script needs no semicolon.
+    fn at_script_end(&self) -> bool {
+        self.app.repl_semantics && self.contexts.is_empty() && self.is_at_end()
+    }
+
+    fn consume_terminator(&mut self, message: &str) -> Option<()> {
+        if self.match_one_of([TokenType::Semicolon]) || self.at_implicit_terminator() {
+            Some(())
+        } else if self.app.friendly_errors {
+            let message = format!("{} (did you forget a semicolon?)", message);
+            self.error(&self.peek_token().clone(), &message);
+            None
+        } else {
+            self.error(&self.peek_token().clone(), message);
             None
         }
     }
 
+    // With newline-terminated statements, a line starting with '(', '[' or '-' outside of
+    // brackets begins a new statement rather than continuing the expression before it.
+    // Other operators can't start a statement, so they always continue it.
+    fn starts_new_statement(&self) -> bool {
+        self.app.newline_terminated
+            && self.nesting == 0
+            && self.on_new_line()
+            && (self.check_token(TokenType::LeftParen)
+                || self.check_token(TokenType::LeftBracket)
+                || self.check_token(TokenType::Minus))
+    }
+
+    fn error(&self, token: &Token, message: &str) {
+        match self.contexts.last() {
+            Some(context) => self
+                .app
+                .error_token(token, &format!("{} (in {})", message, context)),
+            None => self.app.error_token(token, message),
+        }
+    }
+
     fn synchronize(&mut self) {
-        self.advance();
+        // The token that caused the error may already start the next statement. Skipping it
+        // would swallow that statement, so only advance when it doesn't.
+        if !self.at_statement_start() {
+            self.advance();
+        }
 
         while !self.is_at_end() {
-            if self.previous_token().token_type == TokenType::Semicolon {
+            if self.previous_token().token_type == TokenType::Semicolon || self.at_statement_start()
+            {
                 return;
             }
 
-            match self.peek_token().token_type {
-                TokenType::Class
+            self.advance();
+        }
+    }
+
+    fn at_statement_start(&self) -> bool {
+        matches!(
+            self.peek_token().token_type,
+            TokenType::Assert
+                | TokenType::Break
+                | TokenType::Class
+                | TokenType::Continue
+                | TokenType::Enum
                 | TokenType::Fun
                 | TokenType::Var
                 | TokenType::For
+                | TokenType::Foreach
                 | TokenType::If
+                | TokenType::Import
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => return,
-                _ => {}
-            }
-
-            self.advance();
-        }
+                | TokenType::Repeat
+                | TokenType::Return
+                | TokenType::Throw
+                | TokenType::Try
+                | TokenType::Yield
+        )
     }
 
     fn match_one_of<const N: usize>(&mut self, token_types: [TokenType; N]) -> bool {
@@ -610,4 +1202,19 @@ impl Parser<'_> {
     fn previous_token(&self) -> &Token {
         &self.tokens[self.current - 1]
     }
+
+    // Rebuilds the source text of the given tokens, with any whitespace or comments between
+    // them collapsed into a single space.
+    fn source_text(&self, start: usize, end: usize) -> String {
+        let mut text = String::new();
+        let mut previous_end = None;
+        for token in &self.tokens[start..end] {
+            if previous_end.is_some_and(|previous_end| token.start > previous_end) {
+                text.push(' ');
+            }
+            previous_end = Some(token.start + token.len);
+            text.push_str(&self.app.interner.resolve(token.lexeme));
+        }
+        text
+    }
 }
@@ -0,0 +1,56 @@
+use crate::interner::{Interner, Symbol};
+use rustc_hash::FxHashMap;
+use std::time::{Duration, Instant};
+
+// Measures how long each call stack spends in its innermost function, for flame graphs.
+pub struct Profiler {
+    start: Instant,
+    // Functions currently running, outermost first, with the time they were called and the
+    // time spent in the calls they made.
+    frames: Vec<(Symbol, Instant, Duration)>,
+    self_times: FxHashMap<Vec<Symbol>, Duration>,
+    // Time spent in calls made from top-level code.
+    top_level_calls: Duration,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            start: Instant::now(),
+            frames: Vec::new(),
+            self_times: FxHashMap::default(),
+            top_level_calls: Duration::ZERO,
+        }
+    }
+
+    pub fn enter(&mut self, name: Symbol) {
+        self.frames.push((name, Instant::now(), Duration::ZERO));
+    }
+
+    pub fn exit(&mut self) {
+        let stack: Vec<Symbol> = self.frames.iter().map(|(name, ..)| *name).collect();
+        let (_, called, children) = self.frames.pop().expect("Profiler exit without enter");
+        let elapsed = called.elapsed();
+
+        *self.self_times.entry(stack).or_default() += elapsed.saturating_sub(children);
+        match self.frames.last_mut() {
+            Some((_, _, caller_children)) => *caller_children += elapsed,
+            None => self.top_level_calls += elapsed,
+        }
+    }
+
+    // Folded stacks as read by inferno and flamegraph.pl: one line per call stack, with its
+    // frames separated by ';' and followed by the nanoseconds spent in the innermost frame.
+    // Every stack starts at <script>, which stands for top-level code.
+    pub fn folded(&self, interner: &Interner) -> String {
+        let script_time = self.start.elapsed().saturating_sub(self.top_level_calls);
+        let mut lines = vec![format!("<script> {}", script_time.as_nanos())];
+        for (stack, time) in &self.self_times {
+            let frames: Vec<String> = stack.iter().map(|name| interner.resolve(*name)).collect();
+            lines.push(format!("<script>;{} {}", frames.join(";"), time.as_nanos()));
+        }
+        lines.sort();
+
+        lines.join("\n") + "\n"
+    }
+}
@@ -1,10 +1,14 @@
 use crate::{App, Symbol};
+use rustc_hash::FxHashMap;
 use std::str;
 use std::str::FromStr;
+use std::sync::OnceLock;
 
 pub struct Scanner<'a> {
     source: &'a [u8],
     line: u64,
+    // Offsets where each line starts, collected while scanning.
+    line_starts: Vec<usize>,
     start: usize,
     current: usize,
     tokens: Vec<Token>,
@@ -16,6 +20,7 @@ impl Scanner<'_> {
         Scanner {
             source,
             line: 1,
+            line_starts: vec![0],
             start: 0,
             current: 0,
             tokens: vec![],
@@ -25,6 +30,14 @@ impl Scanner<'_> {
 
     pub fn scan_tokens(&mut self) -> Vec<Token> {
         self.tokens.clear();
+        self.line_starts.truncate(1);
+
+        // Skip a shebang line, so scripts can be made executable.
+        if self.source.starts_with(b"#!") {
+            while !is_line_end(self.peek_char()) && !self.is_at_end() {
+                self.advance();
+            }
+        }
 
         while !self.is_at_end() {
             self.start = self.current;
@@ -36,6 +49,8 @@ impl Scanner<'_> {
             lexeme: self.app.interner.get_or_intern("<EOF>"),
             literal: TokenLiteral::Nil,
             line: self.line,
+            start: self.source.len(),
+            len: 0,
         });
 
         self.tokens.clone()
@@ -48,12 +63,16 @@ impl Scanner<'_> {
             b')' => self.add_token(TokenType::RightParen),
             b'{' => self.add_token(TokenType::LeftBrace),
             b'}' => self.add_token(TokenType::RightBrace),
+            b'[' => self.add_token(TokenType::LeftBracket),
+            b']' => self.add_token(TokenType::RightBracket),
             b',' => self.add_token(TokenType::Comma),
             b'.' => self.add_token(TokenType::Dot),
             b'-' => self.add_token(TokenType::Minus),
             b'+' => self.add_token(TokenType::Plus),
             b';' => self.add_token(TokenType::Semicolon),
             b'*' => self.add_token(TokenType::Star),
+            b'?' => self.add_token(TokenType::Question),
+            b':' => self.add_token(TokenType::Colon),
             b'!' => {
                 let token_type = if self.match_char(b'=') {
                     TokenType::BangEqual
@@ -65,6 +84,8 @@ impl Scanner<'_> {
             b'=' => {
                 let token_type = if self.match_char(b'=') {
                     TokenType::EqualEqual
+                } else if self.match_char(b'>') {
+                    TokenType::Arrow
                 } else {
                     TokenType::Equal
                 };
@@ -88,15 +109,21 @@ impl Scanner<'_> {
             }
             b'/' => {
                 if self.match_char(b'/') {
-                    while self.peek_char() != b'\n' && !self.is_at_end() {
+                    while !is_line_end(self.peek_char()) && !self.is_at_end() {
                         self.advance();
                     }
                 } else {
                     self.add_token(TokenType::Slash)
                 }
             }
-            b' ' | b'\r' | b'\t' => {}
-            b'\n' => self.line += 1,
+            b' ' | b'\t' => {}
+            // A lone '\r' ends a line too, while "\r\n" only counts once at the '\n'.
+            b'\r' => {
+                if self.peek_char() != b'\n' {
+                    self.new_line(self.current);
+                }
+            }
+            b'\n' => self.new_line(self.current),
             b'"' => self.string(),
             _ => {
                 if is_digit(c) {
@@ -116,25 +143,10 @@ impl Scanner<'_> {
         }
 
         let text = &self.source[self.start..self.current];
-        let token_type = match text {
-            b"and" => TokenType::And,
-            b"class" => TokenType::Class,
-            b"else" => TokenType::Else,
-            b"false" => TokenType::False,
-            b"for" => TokenType::For,
-            b"fun" => TokenType::Fun,
-            b"if" => TokenType::If,
-            b"nil" => TokenType::Nil,
-            b"or" => TokenType::Or,
-            b"print" => TokenType::Print,
-            b"return" => TokenType::Return,
-            b"super" => TokenType::Super,
-            b"this" => TokenType::This,
-            b"true" => TokenType::True,
-            b"var" => TokenType::Var,
-            b"while" => TokenType::While,
-            _ => TokenType::Identifier,
-        };
+        let token_type = keywords()
+            .get(text)
+            .cloned()
+            .unwrap_or(TokenType::Identifier);
 
         self.add_token(token_type);
     }
@@ -170,9 +182,15 @@ impl Scanner<'_> {
     }
 
     fn string(&mut self) {
+        let start_line = self.line;
         while self.peek_char() != b'"' && !self.is_at_end() {
-            if self.peek_char() == b'\n' {
-                self.line += 1;
+            // An escaped quote or backslash doesn't end the string.
+            if self.peek_char() == b'\\' && matches!(self.peek_next_char(), b'"' | b'\\') {
+                self.advance();
+            } else if self.peek_char() == b'\n'
+                || (self.peek_char() == b'\r' && self.peek_next_char() != b'\n')
+            {
+                self.new_line(self.current + 1);
             }
             self.advance();
         }
@@ -186,17 +204,85 @@ impl Scanner<'_> {
         self.advance();
 
         // Trim the surrounding quotes.
-        let value = &self.source[(self.start + 1)..(self.current - 1)];
+        let Ok(value) = str::from_utf8(&self.source[(self.start + 1)..(self.current - 1)]) else {
+            self.app.error(self.line, "Invalid UTF-8 in string.");
+            return;
+        };
+        let value = self.unescape(value, start_line);
         self.add_token_with_literal(
             TokenType::String,
-            TokenLiteral::String(
-                self.app
-                    .interner
-                    .get_or_intern(str::from_utf8(value).unwrap()),
-            ),
+            TokenLiteral::String(self.app.interner.get_or_intern(value)),
         );
     }
 
+    // Decodes escape sequences, reporting and dropping malformed ones. `line` is the line the
+    // string starts on.
+    fn unescape(&self, value: &str, mut line: u64) -> String {
+        let mut result = String::with_capacity(value.len());
+        let mut chars = value.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                if c == '\n' || (c == '\r' && chars.peek() != Some(&'\n')) {
+                    line += 1;
+                }
+                result.push(c);
+                continue;
+            }
+
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some('r') => result.push('\r'),
+                Some('0') => result.push('\0'),
+                Some('\\') => result.push('\\'),
+                Some('"') => result.push('"'),
+                Some('x') => {
+                    let digits: String = chars.by_ref().take(2).collect();
+                    if digits.len() == 2 && is_hex(&digits) {
+                        let code = u32::from_str_radix(&digits, 16).unwrap();
+                        result.push(char::from_u32(code).unwrap());
+                    } else {
+                        self.app.error(line, "Expect two hex digits after '\\x'.");
+                    }
+                }
+                Some('u') => {
+                    let mut digits = String::new();
+                    let closed = chars.next() == Some('{')
+                        && loop {
+                            match chars.next() {
+                                Some('}') => break true,
+                                Some(c) if c.is_ascii_hexdigit() && digits.len() < 6 => {
+                                    digits.push(c)
+                                }
+                                _ => break false,
+                            }
+                        };
+
+                    if !closed || digits.is_empty() {
+                        self.app
+                            .error(line, "Expect '{', 1 to 6 hex digits and '}' after '\\u'.");
+                    } else if let Some(c) =
+                        char::from_u32(u32::from_str_radix(&digits, 16).unwrap())
+                    {
+                        result.push(c);
+                    } else {
+                        self.app
+                            .error(line, "Unicode escape is not a valid code point.");
+                    }
+                }
+                other => {
+                    self.app.error(line, "Invalid escape sequence.");
+                    if other == Some('\n') {
+                        line += 1;
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
     fn match_char(&mut self, expected: u8) -> bool {
         if self.is_at_end() || self.peek_char() != expected {
             false
@@ -214,6 +300,19 @@ impl Scanner<'_> {
         }
     }
 
+    pub fn line_index(&self) -> LineIndex {
+        LineIndex {
+            line_starts: self.line_starts.clone(),
+            source_len: self.source.len(),
+        }
+    }
+
+    // Counts a line break, after which the next line starts at `next_line_start`.
+    fn new_line(&mut self, next_line_start: usize) {
+        self.line += 1;
+        self.line_starts.push(next_line_start);
+    }
+
     fn advance(&mut self) -> u8 {
         let c = self.source[self.current];
         self.current += 1;
@@ -231,6 +330,8 @@ impl Scanner<'_> {
             lexeme: self.app.interner.get_or_intern(lexeme),
             literal,
             line: self.line,
+            start: self.start,
+            len: self.current - self.start,
         })
     }
 
@@ -242,34 +343,49 @@ impl Scanner<'_> {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TokenType {
     And,
+    Arrow,
+    Assert,
     Bang,
     BangEqual,
+    Break,
+    Catch,
     Class,
+    Colon,
     Comma,
+    Continue,
     Dot,
     Else,
+    Enum,
     Eof,
     Equal,
     EqualEqual,
     False,
     For,
+    Foreach,
     Fun,
+    Global,
     Greater,
     GreaterEqual,
     Identifier,
     If,
+    Import,
     LeftBrace,
+    LeftBracket,
     LeftParen,
     Less,
     LessEqual,
+    Match,
     Minus,
     Nil,
     Number,
     Or,
     Plus,
     Print,
+    Question,
+    Repeat,
     Return,
     RightBrace,
+    RightBracket,
     RightParen,
     Semicolon,
     Slash,
@@ -277,9 +393,40 @@ pub enum TokenType {
     String,
     Super,
     This,
+    Throw,
     True,
+    Try,
+    TypeOf,
     Var,
     While,
+    Yield,
+}
+
+// Where the lines of a scanned source start, for mapping byte offsets to lines and columns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineIndex {
+    line_starts: Vec<usize>,
+    source_len: usize,
+}
+
+impl LineIndex {
+    // Returns the line and column of a byte offset, both starting at 1. Columns count bytes.
+    pub fn position(&self, offset: usize) -> (u64, usize) {
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        (line as u64, offset - self.line_starts[line - 1] + 1)
+    }
+
+    // Returns the column a token starts at. Tokens that can't come from this source, and
+    // strings that start on an earlier line than the one they report, have none.
+    pub fn column(&self, token: &Token) -> Option<usize> {
+        if token.start + token.len > self.source_len {
+            return None;
+        }
+        match self.position(token.start) {
+            (line, column) if line == token.line => Some(column),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -288,6 +435,9 @@ pub struct Token {
     pub lexeme: Symbol,
     pub literal: TokenLiteral,
     pub line: u64,
+    // Byte range of the lexeme in the source.
+    pub start: usize,
+    pub len: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -298,8 +448,54 @@ pub enum TokenLiteral {
     Nil,
 }
 
+fn keywords() -> &'static FxHashMap<&'static [u8], TokenType> {
+    static KEYWORDS: OnceLock<FxHashMap<&'static [u8], TokenType>> = OnceLock::new();
+    KEYWORDS.get_or_init(|| {
+        FxHashMap::from_iter([
+            (&b"and"[..], TokenType::And),
+            (b"assert", TokenType::Assert),
+            (b"break", TokenType::Break),
+            (b"catch", TokenType::Catch),
+            (b"class", TokenType::Class),
+            (b"continue", TokenType::Continue),
+            (b"else", TokenType::Else),
+            (b"enum", TokenType::Enum),
+            (b"false", TokenType::False),
+            (b"for", TokenType::For),
+            (b"foreach", TokenType::Foreach),
+            (b"fun", TokenType::Fun),
+            (b"global", TokenType::Global),
+            (b"if", TokenType::If),
+            (b"import", TokenType::Import),
+            (b"match", TokenType::Match),
+            (b"nil", TokenType::Nil),
+            (b"or", TokenType::Or),
+            (b"print", TokenType::Print),
+            (b"repeat", TokenType::Repeat),
+            (b"return", TokenType::Return),
+            (b"super", TokenType::Super),
+            (b"this", TokenType::This),
+            (b"throw", TokenType::Throw),
+            (b"true", TokenType::True),
+            (b"try", TokenType::Try),
+            (b"typeof", TokenType::TypeOf),
+            (b"var", TokenType::Var),
+            (b"while", TokenType::While),
+            (b"yield", TokenType::Yield),
+        ])
+    })
+}
+
+fn is_line_end(c: u8) -> bool {
+    c == b'\n' || c == b'\r'
+}
+
+fn is_hex(digits: &str) -> bool {
+    digits.chars().all(|c| c.is_ascii_hexdigit())
+}
+
 fn is_alpha(c: u8) -> bool {
-    (b'a'..=b'z').contains(&c) || (b'A'..=b'Z').contains(&c) || c == b'_'
+    c.is_ascii_alphabetic() || c == b'_'
 }
 
 fn is_alpha_numeric(c: u8) -> bool {
@@ -307,5 +503,5 @@ fn is_alpha_numeric(c: u8) -> bool {
 }
 
 fn is_digit(c: u8) -> bool {
-    (b'0'..=b'9').contains(&c)
+    c.is_ascii_digit()
 }